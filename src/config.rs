@@ -0,0 +1,152 @@
+// Copyright (c) 2022-2024 Yuki Kishimoto
+// Distributed under the MIT software license
+
+//! WebSocket config
+
+#[cfg(not(target_arch = "wasm32"))]
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig as TungsteniteWebSocketConfig;
+
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 << 20; // 64 MiB
+const DEFAULT_MAX_FRAME_SIZE: usize = 16 << 20; // 16 MiB
+const DEFAULT_WRITE_BUFFER_SIZE: usize = 128 * 1024; // 128 KiB
+
+/// Close code sent when a received message exceeds the max message size
+#[cfg(target_arch = "wasm32")]
+pub(crate) const MESSAGE_TOO_BIG: u16 = 1009;
+
+/// WebSocket protocol config
+///
+/// The defaults are the same of `tungstenite`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct WebSocketConfig {
+    pub(crate) max_message_size: Option<usize>,
+    pub(crate) max_frame_size: Option<usize>,
+    pub(crate) write_buffer_size: usize,
+    pub(crate) max_write_buffer_size: usize,
+    pub(crate) accept_unmasked_frames: bool,
+}
+
+impl Default for WebSocketConfig {
+    fn default() -> Self {
+        Self {
+            max_message_size: Some(DEFAULT_MAX_MESSAGE_SIZE),
+            max_frame_size: Some(DEFAULT_MAX_FRAME_SIZE),
+            write_buffer_size: DEFAULT_WRITE_BUFFER_SIZE,
+            max_write_buffer_size: usize::MAX,
+            accept_unmasked_frames: false,
+        }
+    }
+}
+
+impl WebSocketConfig {
+    /// New default config
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the max size of an incoming message (default: 64 MiB)
+    ///
+    /// `None` means no size limit.
+    #[inline]
+    pub fn max_message_size(mut self, size: Option<usize>) -> Self {
+        self.max_message_size = size;
+        self
+    }
+
+    /// Set the max size of a single incoming frame (default: 16 MiB)
+    ///
+    /// `None` means no size limit. Ignored in WASM environments.
+    #[inline]
+    pub fn max_frame_size(mut self, size: Option<usize>) -> Self {
+        self.max_frame_size = size;
+        self
+    }
+
+    /// Set the target minimum size of the write buffer to reach before writing the data to the
+    /// underlying stream (default: 128 KiB)
+    ///
+    /// Ignored in WASM environments.
+    #[inline]
+    pub fn write_buffer_size(mut self, size: usize) -> Self {
+        self.write_buffer_size = size;
+        self
+    }
+
+    /// Set the max size of the write buffer in bytes (default: unlimited)
+    ///
+    /// Must be greater than the write buffer size: smaller values are raised to the write buffer size + 1.
+    /// Ignored in WASM environments.
+    #[inline]
+    pub fn max_write_buffer_size(mut self, size: usize) -> Self {
+        self.max_write_buffer_size = size;
+        self
+    }
+
+    /// Accept unmasked frames from the client (default: false)
+    ///
+    /// Only relevant for servers, as required by RFC 6455. Ignored in WASM environments.
+    #[inline]
+    pub fn accept_unmasked_frames(mut self, accept: bool) -> Self {
+        self.accept_unmasked_frames = accept;
+        self
+    }
+
+    /// Check the size of an incoming message
+    ///
+    /// If the max message size is exceeded, returns it: the message must be discarded and
+    /// the connection failed with the 1009 (message too big) close code, like `tungstenite` does.
+    #[cfg(any(target_arch = "wasm32", test))]
+    pub(crate) fn check_message_size(&self, size: usize) -> Result<(), usize> {
+        match self.max_message_size {
+            Some(max_size) if size > max_size => Err(max_size),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl From<WebSocketConfig> for TungsteniteWebSocketConfig {
+    fn from(config: WebSocketConfig) -> Self {
+        // `tungstenite` panics if the max write buffer size isn't greater than the write buffer size
+        let write_buffer_size: usize = config.write_buffer_size.min(usize::MAX - 1);
+        let max_write_buffer_size: usize = config.max_write_buffer_size.max(write_buffer_size + 1);
+
+        Self::default()
+            .max_message_size(config.max_message_size)
+            .max_frame_size(config.max_frame_size)
+            .write_buffer_size(write_buffer_size)
+            .max_write_buffer_size(max_write_buffer_size)
+            .accept_unmasked_frames(config.accept_unmasked_frames)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(not(target_arch = "wasm32"))]
+    fn test_invalid_write_buffer_size() {
+        let config = WebSocketConfig::new()
+            .write_buffer_size(1024)
+            .max_write_buffer_size(512);
+        let config = TungsteniteWebSocketConfig::from(config);
+        assert_eq!(config.write_buffer_size, 1024);
+        assert_eq!(config.max_write_buffer_size, 1025);
+
+        let config = WebSocketConfig::new().write_buffer_size(usize::MAX);
+        let config = TungsteniteWebSocketConfig::from(config);
+        assert!(config.max_write_buffer_size > config.write_buffer_size);
+    }
+
+    #[test]
+    fn test_check_message_size() {
+        let config = WebSocketConfig::new().max_message_size(Some(16));
+        assert_eq!(config.check_message_size(16), Ok(()));
+        assert_eq!(config.check_message_size(17), Err(16));
+
+        let config = WebSocketConfig::new().max_message_size(None);
+        assert_eq!(config.check_message_size(usize::MAX), Ok(()));
+    }
+}
@@ -0,0 +1,61 @@
+// Copyright (c) 2022-2024 Yuki Kishimoto
+// Distributed under the MIT software license
+
+//! Errors shared by the native and WASM targets
+
+use core::fmt;
+
+/// Subprotocol negotiation error
+///
+/// The same checks are performed on all the targets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SubProtocolError {
+    /// The server selected a subprotocol, but none was requested
+    ServerSentSubProtocolNoneRequested,
+    /// The server selected a subprotocol that was not requested
+    InvalidSubProtocol,
+    /// Subprotocols were requested, but the server didn't select any
+    NoSubProtocol,
+}
+
+impl std::error::Error for SubProtocolError {}
+
+impl fmt::Display for SubProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ServerSentSubProtocolNoneRequested => {
+                write!(
+                    f,
+                    "the server selected a subprotocol, but none was requested"
+                )
+            }
+            Self::InvalidSubProtocol => {
+                write!(
+                    f,
+                    "the server selected a subprotocol that was not requested"
+                )
+            }
+            Self::NoSubProtocol => {
+                write!(
+                    f,
+                    "subprotocols were requested, but the server didn't select any"
+                )
+            }
+        }
+    }
+}
+
+impl SubProtocolError {
+    /// Check the subprotocol selected by the server against the requested ones
+    #[cfg(target_arch = "wasm32")]
+    pub(crate) fn check(requested: &[String], selected: Option<&str>) -> Result<(), Self> {
+        match selected {
+            Some(..) if requested.is_empty() => Err(Self::ServerSentSubProtocolNoneRequested),
+            Some(protocol) if !requested.iter().any(|p| p == protocol) => {
+                Err(Self::InvalidSubProtocol)
+            }
+            None if !requested.is_empty() => Err(Self::NoSubProtocol),
+            _ => Ok(()),
+        }
+    }
+}
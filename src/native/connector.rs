@@ -0,0 +1,54 @@
+// Copyright (c) 2022-2024 Yuki Kishimoto
+// Distributed under the MIT software license
+
+//! Connector
+
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+use std::{fmt, io};
+
+use super::resolver::BoxedFuture;
+use super::stream::BoxedStream;
+
+/// Custom transport, used with [`ConnectionMode::Custom`](crate::ConnectionMode::Custom)
+///
+/// The TLS handshake (for `wss` URLs) and the WebSocket upgrade are performed over the returned stream.
+pub trait Connector: fmt::Debug + Send + Sync {
+    /// Open a byte stream to the host and port of the URL
+    ///
+    /// The host is a domain or an IP address (IPv6 addresses are without brackets).
+    fn connect<'a>(&'a self, host: &'a str, port: u16) -> BoxedFuture<'a, io::Result<BoxedStream>>;
+}
+
+// Connectors are compared by identity, so that `ConnectionMode` keeps its traits
+
+impl PartialEq for dyn Connector {
+    fn eq(&self, other: &Self) -> bool {
+        addr(self) == addr(other)
+    }
+}
+
+impl Eq for dyn Connector {}
+
+impl PartialOrd for dyn Connector {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for dyn Connector {
+    fn cmp(&self, other: &Self) -> Ordering {
+        addr(self).cmp(&addr(other))
+    }
+}
+
+impl Hash for dyn Connector {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        addr(self).hash(state);
+    }
+}
+
+#[inline]
+fn addr(connector: &dyn Connector) -> *const () {
+    connector as *const dyn Connector as *const ()
+}
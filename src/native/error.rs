@@ -3,22 +3,70 @@
 
 use core::fmt;
 
+use tokio_tungstenite::tungstenite::error::{
+    ProtocolError, SubProtocolError as WsSubProtocolError,
+};
 use tokio_tungstenite::tungstenite::Error as WsError;
 use url::ParseError;
 
+use super::ProxyDns;
+use crate::{SubProtocolError, TimeoutPhase};
+
 #[derive(Debug)]
 pub enum Error {
     /// Ws error
     Ws(WsError),
+    /// Subprotocol negotiation failed (i.e., the server selected a subprotocol that was not requested)
+    SubProtocol(SubProtocolError),
     /// I/O error
     Io(std::io::Error),
     /// Socks error
     #[cfg(feature = "socks")]
     Socks(tokio_socks::Error),
+    /// The proxy DNS resolution mode doesn't allow to connect to the host
+    ProxyDns {
+        /// Target host
+        host: String,
+        /// Proxy DNS resolution mode
+        dns: ProxyDns,
+    },
+    /// Invalid or unsupported proxy URL
+    InvalidProxyUrl(String),
+    /// The HTTP proxy refused the `CONNECT` request
+    ProxyConnect {
+        /// Response status code
+        status: u16,
+        /// Response reason phrase
+        reason: String,
+    },
+    /// A hop of the proxy chain failed
+    ProxyHop {
+        /// Index of the hop in the chain
+        hop: usize,
+        /// Hop error
+        error: Box<Error>,
+    },
+    /// Tor control port error
+    #[cfg(feature = "tor")]
+    TorControl {
+        /// Reply status code, if the error was reported by Tor
+        code: Option<u16>,
+        /// Error message
+        message: String,
+    },
     /// Url parse error
     Url(ParseError),
+    /// Invalid client certificate or private key
+    InvalidClientIdentity(String),
+    /// The server rejected the client certificate set with [`TlsConfig::client_identity`](crate::native::TlsConfig::client_identity)
+    ClientCertificateRejected,
+    /// None of the certificates presented by the server matches the pins
+    CertificatePinMismatch {
+        /// The SPKI SHA-256 hashes of the server certificates that can be pinned
+        presented: Vec<[u8; 32]>,
+    },
     /// Timeout
-    Timeout,
+    Timeout(TimeoutPhase),
 }
 
 impl std::error::Error for Error {}
@@ -27,18 +75,51 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Ws(e) => write!(f, "{e}"),
+            Self::SubProtocol(e) => write!(f, "{e}"),
             Self::Io(e) => write!(f, "{e}"),
             #[cfg(feature = "socks")]
             Self::Socks(e) => write!(f, "{e}"),
+            Self::ProxyDns { host, dns } => write!(
+                f,
+                "can't connect to '{host}' through the proxy with {dns} DNS resolution"
+            ),
+            Self::InvalidProxyUrl(e) => write!(f, "invalid proxy URL: {e}"),
+            Self::ProxyConnect { status, reason } => {
+                write!(f, "proxy CONNECT failed: {status} {reason}")
+            }
+            Self::ProxyHop { hop, error } => write!(f, "proxy hop #{hop} failed: {error}"),
+            #[cfg(feature = "tor")]
+            Self::TorControl { code, message } => match code {
+                Some(code) => write!(f, "Tor control error {code}: {message}"),
+                None => write!(f, "Tor control error: {message}"),
+            },
             Self::Url(e) => write!(f, "{e}"),
-            Self::Timeout => write!(f, "timeout"),
+            Self::InvalidClientIdentity(e) => write!(f, "invalid client identity: {e}"),
+            Self::ClientCertificateRejected => {
+                write!(f, "the server rejected the client certificate")
+            }
+            Self::CertificatePinMismatch { .. } => {
+                write!(f, "the server certificate doesn't match any pin")
+            }
+            Self::Timeout(phase) => write!(f, "{phase} timeout"),
         }
     }
 }
 
 impl From<WsError> for Error {
     fn from(e: WsError) -> Self {
-        Self::Ws(e)
+        match e {
+            WsError::Protocol(ProtocolError::SecWebSocketSubProtocolError(e)) => {
+                Self::SubProtocol(match e {
+                    WsSubProtocolError::ServerSentSubProtocolNoneRequested => {
+                        SubProtocolError::ServerSentSubProtocolNoneRequested
+                    }
+                    WsSubProtocolError::InvalidSubProtocol => SubProtocolError::InvalidSubProtocol,
+                    WsSubProtocolError::NoSubProtocol => SubProtocolError::NoSubProtocol,
+                })
+            }
+            e => Self::Ws(e),
+        }
     }
 }
 
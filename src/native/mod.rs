@@ -3,104 +3,308 @@
 
 //! Native
 
-#[cfg(feature = "socks")]
-use std::net::SocketAddr;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+#[cfg(unix)]
+use std::path::Path;
+use std::time::Duration;
 
 use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpStream;
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+pub use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+pub use tokio_tungstenite::tungstenite::handshake::client::Request;
+use tokio_tungstenite::tungstenite::handshake::client::Response;
+use tokio_tungstenite::tungstenite::http::header::{
+    HeaderMap, HeaderName, HeaderValue, SEC_WEBSOCKET_PROTOCOL,
+};
 use tokio_tungstenite::tungstenite::protocol::Role;
-pub use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::Error as WsError;
+pub use tokio_tungstenite::tungstenite::{http, Message};
 use tokio_tungstenite::MaybeTlsStream;
 pub use tokio_tungstenite::WebSocketStream;
-use url::Url;
+use url::{Host, Url};
 
+mod connector;
 mod error;
-#[cfg(feature = "socks")]
-mod socks;
+mod proxy;
+mod resolver;
+mod stream;
+mod tcp;
+mod tls;
 
+pub use self::connector::Connector;
 pub use self::error::Error;
-#[cfg(feature = "socks")]
-use self::socks::TcpSocks5Stream;
+pub(crate) use self::proxy::from_env as proxy_from_env;
+pub use self::proxy::{Proxy, ProxyCredentials, ProxyDns, ProxyProtocol};
+#[cfg(feature = "tor")]
+pub use self::proxy::{Tor, TorAuth, TorControl};
+pub use self::resolver::{BoxedFuture, CachingResolver, Resolver, StaticResolver, SystemResolver};
+pub use self::stream::{AsyncStream, BoxedStream};
+pub use self::tcp::{HappyEyeballsConfig, IpPreference, TcpOptions};
+#[cfg(feature = "__rustls")]
+pub use self::tls::rustls;
+pub use self::tls::{ClientIdentity, TlsConfig};
 use crate::socket::WebSocket;
-use crate::ConnectionMode;
+use crate::{ConnectOptions, ConnectionMode, HandshakeResponse, TimeoutPhase, WebSocketConfig};
 
-pub async fn connect(url: &Url, mode: &ConnectionMode) -> Result<WebSocket, Error> {
-    match mode {
-        ConnectionMode::Direct => connect_direct(url).await,
-        #[cfg(feature = "socks")]
-        ConnectionMode::Proxy(proxy) => connect_proxy(url, *proxy).await,
+pub async fn connect(url: &Url, opts: &ConnectOptions) -> Result<WebSocket, Error> {
+    let request: Request = url.as_str().into_client_request()?;
+    connect_request(request, opts).await
+}
+
+/// Connect using a custom handshake request
+///
+/// The request must be a valid WebSocket handshake request: build it with [`IntoClientRequest`]
+/// and then customize it. The headers of the [`ConnectOptions`] are merged into the request,
+/// replacing the ones with the same name.
+pub async fn connect_request(request: Request, opts: &ConnectOptions) -> Result<WebSocket, Error> {
+    let url: Url = Url::parse(&request.uri().to_string()).map_err(Error::Url)?;
+    let request: Request = client_request(request, opts)?;
+
+    timeout(
+        opts.timeout,
+        TimeoutPhase::Total,
+        connect_with_mode(&url, request, opts),
+    )
+    .await
+}
+
+/// Connect over an already established stream
+///
+/// Performs the TLS handshake for `wss` URLs and the WebSocket upgrade. The connection mode is ignored.
+pub async fn connect_over<S>(
+    url: &Url,
+    stream: S,
+    opts: &ConnectOptions,
+) -> Result<WebSocket, Error>
+where
+    S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    let request: Request = url.as_str().into_client_request()?;
+    let request: Request = client_request(request, opts)?;
+
+    timeout(
+        opts.timeout,
+        TimeoutPhase::Total,
+        connect_stream(url, request, opts, Box::new(stream)),
+    )
+    .await
+}
+
+async fn connect_with_mode(
+    url: &Url,
+    request: Request,
+    opts: &ConnectOptions,
+) -> Result<WebSocket, Error> {
+    match &opts.mode {
+        ConnectionMode::Direct => connect_direct(url, request, opts).await,
+        ConnectionMode::Proxy(proxy) => {
+            let tunnel = Box::pin(proxy::connect(url, opts, proxy));
+            connect_proxy(url, request, opts, tunnel).await
+        }
+        ConnectionMode::ProxyChain(chain) if chain.is_empty() => {
+            connect_direct(url, request, opts).await
+        }
+        ConnectionMode::ProxyChain(chain) => {
+            let tunnel = Box::pin(proxy::connect_chain(url, opts, chain));
+            connect_proxy(url, request, opts, tunnel).await
+        }
+        #[cfg(unix)]
+        ConnectionMode::Unix(path) => connect_unix(url, request, opts, path).await,
+        ConnectionMode::Custom(connector) => {
+            connect_custom(url, request, opts, connector.as_ref()).await
+        }
     }
 }
 
-async fn connect_direct(url: &Url) -> Result<WebSocket, Error> {
-    let host: &str = url.host_str().ok_or_else(Error::empty_host)?;
-    let port: u16 = url
-        .port_or_known_default()
-        .ok_or_else(Error::invalid_port)?;
+async fn connect_direct(
+    url: &Url,
+    request: Request,
+    opts: &ConnectOptions,
+) -> Result<WebSocket, Error> {
+    let tcp_stream: TcpStream = timeout(opts.connect_timeout, TimeoutPhase::Connect, async {
+        let addrs: Vec<SocketAddr> = resolve(url, opts).await?;
+        Ok(tcp::connect(&addrs, opts).await?)
+    })
+    .await?;
 
-    let host: String = format!("{}:{}", host, port);
+    connect_stream(url, request, opts, Box::new(tcp_stream)).await
+}
 
-    let tcp_stream: TcpStream = tokio_happy_eyeballs::connect(host).await?;
+#[cfg(unix)]
+async fn connect_unix(
+    url: &Url,
+    request: Request,
+    opts: &ConnectOptions,
+    path: &Path,
+) -> Result<WebSocket, Error> {
+    let stream: UnixStream = timeout(opts.connect_timeout, TimeoutPhase::Connect, async {
+        Ok(UnixStream::connect(path).await?)
+    })
+    .await?;
+
+    connect_stream(url, request, opts, Box::new(stream)).await
+}
+
+async fn connect_custom(
+    url: &Url,
+    request: Request,
+    opts: &ConnectOptions,
+    connector: &dyn Connector,
+) -> Result<WebSocket, Error> {
+    let stream: BoxedStream = timeout(opts.connect_timeout, TimeoutPhase::Connect, async {
+        let host: String = match url.host() {
+            Some(Host::Domain(domain)) => domain.to_string(),
+            Some(Host::Ipv4(ip)) => ip.to_string(),
+            Some(Host::Ipv6(ip)) => ip.to_string(),
+            None => return Err(Error::empty_host()),
+        };
+        let port: u16 = url
+            .port_or_known_default()
+            .ok_or_else(Error::invalid_port)?;
+        Ok(connector.connect(&host, port).await?)
+    })
+    .await?;
 
-    connect_stream(url, tcp_stream).await
+    connect_stream(url, request, opts, stream).await
 }
 
-#[cfg(feature = "socks")]
-async fn connect_proxy(url: &Url, proxy: SocketAddr) -> Result<WebSocket, Error> {
-    let host: &str = url.host_str().ok_or_else(Error::empty_host)?;
+/// Get the addresses to connect to
+///
+/// The explicit addresses take precedence over the DNS resolution of the URL host.
+async fn resolve(url: &Url, opts: &ConnectOptions) -> Result<Vec<SocketAddr>, Error> {
+    if !opts.connect_to.is_empty() {
+        return Ok(opts.connect_to.clone());
+    }
+
+    let host: Host<&str> = url.host().ok_or_else(Error::empty_host)?;
     let port: u16 = url
         .port_or_known_default()
         .ok_or_else(Error::invalid_port)?;
-    let addr: String = format!("{host}:{port}");
 
-    let conn: TcpStream = TcpSocks5Stream::connect(proxy, addr).await?;
-    connect_stream(url, conn).await
+    resolve_host(host, port, opts).await
+}
+
+/// Resolve the host with the configured resolver
+async fn resolve_host(
+    host: Host<&str>,
+    port: u16,
+    opts: &ConnectOptions,
+) -> Result<Vec<SocketAddr>, Error> {
+    let ips: Vec<IpAddr> = match host {
+        Host::Domain(domain) => match &opts.resolver {
+            Some(resolver) => resolver.resolve(domain).await?,
+            None => SystemResolver.resolve(domain).await?,
+        },
+        Host::Ipv4(ip) => vec![IpAddr::V4(ip)],
+        Host::Ipv6(ip) => vec![IpAddr::V6(ip)],
+    };
+
+    Ok(ips
+        .into_iter()
+        .map(|ip| SocketAddr::new(ip, port))
+        .collect())
+}
+
+/// Connect through the proxy tunnel
+///
+/// The tunnel applies the proxy timeout itself.
+async fn connect_proxy<F>(
+    url: &Url,
+    request: Request,
+    opts: &ConnectOptions,
+    tunnel: F,
+) -> Result<WebSocket, Error>
+where
+    F: Future<Output = Result<BoxedStream, Error>>,
+{
+    let stream: BoxedStream = tunnel.await?;
+    connect_stream(url, request, opts, stream).await
+}
+
+async fn connect_stream(
+    url: &Url,
+    request: Request,
+    opts: &ConnectOptions,
+    stream: BoxedStream,
+) -> Result<WebSocket, Error> {
+    let stream: MaybeTlsStream<BoxedStream> = timeout(
+        opts.tls_timeout,
+        TimeoutPhase::Tls,
+        tls::wrap_stream(url, stream, &opts.tls),
+    )
+    .await
+    .map_err(|e| tls::map_error(&opts.tls, e))?;
+
+    let (stream, response) = timeout(
+        opts.handshake_timeout,
+        TimeoutPhase::Handshake,
+        client_async(request, stream, opts.config),
+    )
+    .await
+    .map_err(|e| tls::map_error(&opts.tls, e))?;
+
+    // The selected subprotocol has already been verified during the handshake
+    let response: HandshakeResponse = HandshakeResponse::native(response);
+
+    Ok(WebSocket::tokio(Box::new(stream), response))
 }
 
-async fn connect_stream(url: &Url, stream: TcpStream) -> Result<WebSocket, Error> {
-    let stream = client_async(url, stream).await?;
-    Ok(WebSocket::tokio(Box::new(stream)))
+/// Merge the extra headers and the requested subprotocols into the handshake request
+fn client_request(mut request: Request, opts: &ConnectOptions) -> Result<Request, Error> {
+    let mut extra: HeaderMap = HeaderMap::with_capacity(opts.headers.len());
+
+    for (name, value) in opts.headers.iter() {
+        let name: HeaderName = HeaderName::from_bytes(name.as_bytes()).map_err(WsError::from)?;
+        let value: HeaderValue = HeaderValue::from_str(value).map_err(WsError::from)?;
+        extra.append(name, value);
+    }
+
+    // Replace the headers with the same name (i.e., `Host`), keeping the repeated ones
+    let headers: &mut HeaderMap = request.headers_mut();
+    headers.extend(extra);
+
+    if !opts.protocols.is_empty() {
+        let protocols: String = opts.protocols.join(", ");
+        let value: HeaderValue = HeaderValue::from_str(&protocols).map_err(WsError::from)?;
+        headers.insert(SEC_WEBSOCKET_PROTOCOL, value);
+    }
+
+    Ok(request)
 }
 
 // NOT REMOVE `Box::pin`!
 // Use `Box::pin` to fix stack overflow on windows targets due to large `Future`
-#[cfg(any(
-    feature = "native-tls",
-    feature = "native-tls-vendored",
-    feature = "rustls-tls-native-roots",
-    feature = "rustls-tls-webpki-roots"
-))]
 async fn client_async(
-    url: &Url,
-    stream: TcpStream,
-) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>, Error> {
-    let (stream, _) = Box::pin(tokio_tungstenite::client_async_tls(url.as_str(), stream)).await?;
-    Ok(stream)
-}
-
-#[cfg(not(any(
-    feature = "native-tls",
-    feature = "native-tls-vendored",
-    feature = "rustls-tls-native-roots",
-    feature = "rustls-tls-webpki-roots"
-)))]
-async fn client_async(
-    url: &Url,
-    stream: TcpStream,
-) -> Result<WebSocketStream<MaybeTlsStream<TcpStream>>, Error> {
-    if url.scheme() == "wss" {
-        return Err(tokio_tungstenite::tungstenite::Error::Url(
-            tokio_tungstenite::tungstenite::error::UrlError::TlsFeatureNotEnabled,
-        )
-        .into());
-    }
-
-    let (stream, _) = Box::pin(tokio_tungstenite::client_async(
-        url.as_str(),
-        MaybeTlsStream::Plain(stream),
+    request: Request,
+    stream: MaybeTlsStream<BoxedStream>,
+    config: WebSocketConfig,
+) -> Result<(WebSocketStream<MaybeTlsStream<BoxedStream>>, Response), Error> {
+    Ok(Box::pin(tokio_tungstenite::client_async_with_config(
+        request,
+        stream,
+        Some(config.into()),
     ))
-    .await?;
-    Ok(stream)
+    .await?)
+}
+
+/// Run the future until completion or until the timeout expires
+async fn timeout<F, T>(
+    duration: Option<Duration>,
+    phase: TimeoutPhase,
+    future: F,
+) -> Result<T, Error>
+where
+    F: Future<Output = Result<T, Error>>,
+{
+    match duration {
+        Some(duration) => tokio::time::timeout(duration, future)
+            .await
+            .map_err(|_| Error::Timeout(phase))?,
+        None => future.await,
+    }
 }
 
 #[inline]
@@ -111,6 +315,26 @@ where
     Ok(tokio_tungstenite::accept_async(raw_stream).await?)
 }
 
+/// Accept a websocket connection using a custom [`WebSocketConfig`]
+#[inline]
+pub async fn accept_with_config<S>(
+    raw_stream: S,
+    config: WebSocketConfig,
+) -> Result<WebSocketStream<S>, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    Ok(tokio_tungstenite::accept_async_with_config(raw_stream, Some(config.into())).await?)
+}
+
+/// Accept a websocket connection on a Unix domain socket
+#[inline]
+#[cfg(unix)]
+pub async fn accept_unix(listener: &UnixListener) -> Result<WebSocketStream<UnixStream>, Error> {
+    let (stream, _) = listener.accept().await?;
+    accept(stream).await
+}
+
 /// Take an already upgraded websocket connection
 ///
 /// Useful for when using [hyper] or [warp] or any other HTTP server
@@ -121,3 +345,425 @@ where
 {
     WebSocketStream::from_raw_socket(raw_stream, Role::Server, None).await
 }
+
+/// Take an already upgraded websocket connection using a custom [`WebSocketConfig`]
+#[inline]
+pub async fn take_upgraded_with_config<S>(
+    raw_stream: S,
+    config: WebSocketConfig,
+) -> WebSocketStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    WebSocketStream::from_raw_socket(raw_stream, Role::Server, Some(config.into())).await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::Arc;
+
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::tungstenite::handshake::server::{
+        Request as ServerRequest, Response as ServerResponse,
+    };
+
+    use super::proxy::http::tests::spawn_http_proxy;
+    use super::*;
+    use crate::SubProtocolError;
+
+    /// Spawn a server that selects the given subprotocol (none if empty)
+    async fn spawn_server(protocol: &'static str) -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let callback = |_: &ServerRequest, mut response: ServerResponse| {
+                if !protocol.is_empty() {
+                    response
+                        .headers_mut()
+                        .insert(SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_static(protocol));
+                }
+                Ok(response)
+            };
+            let _ = tokio_tungstenite::accept_hdr_async(stream, callback).await;
+        });
+
+        Url::parse(&format!("ws://{addr}")).unwrap()
+    }
+
+    #[test]
+    fn test_client_request_headers() {
+        let url = Url::parse("wss://relay.example.com/path").unwrap();
+        let request: Request = url.as_str().into_client_request().unwrap();
+        let opts = ConnectOptions::new()
+            .header("Host", "gateway.example.com")
+            .header("Cookie", "a=1")
+            .header("Cookie", "b=2")
+            .header("Authorization", "Bearer token");
+
+        let request: Request = client_request(request, &opts).unwrap();
+        let headers: &HeaderMap = request.headers();
+
+        assert_eq!(headers.get_all("host").iter().count(), 1);
+        assert_eq!(headers["host"], "gateway.example.com");
+        assert_eq!(
+            headers.get_all("cookie").iter().collect::<Vec<_>>(),
+            ["a=1", "b=2"]
+        );
+        assert_eq!(headers["authorization"], "Bearer token");
+        assert!(headers.contains_key("sec-websocket-key"));
+    }
+
+    #[tokio::test]
+    async fn test_custom_headers() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("ws://{}", listener.local_addr().unwrap())).unwrap();
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut headers: HeaderMap = HeaderMap::new();
+            let callback = |request: &ServerRequest, response: ServerResponse| {
+                headers = request.headers().clone();
+                Ok(response)
+            };
+            let _ = tokio_tungstenite::accept_hdr_async(stream, callback).await;
+            headers
+        });
+
+        let opts = ConnectOptions::new()
+            .header("Authorization", "Bearer token")
+            .header("Cookie", "a=1")
+            .header("Cookie", "b=2");
+        connect(&url, &opts).await.unwrap();
+
+        let headers: HeaderMap = server.await.unwrap();
+        assert_eq!(headers["authorization"], "Bearer token");
+        assert_eq!(
+            headers.get_all("cookie").iter().collect::<Vec<_>>(),
+            ["a=1", "b=2"]
+        );
+    }
+
+    #[test]
+    fn test_client_request_invalid_header() {
+        let url = Url::parse("ws://relay.example.com").unwrap();
+        let request: Request = url.as_str().into_client_request().unwrap();
+        let opts = ConnectOptions::new().header("Invalid Name", "value");
+
+        assert!(matches!(
+            client_request(request, &opts),
+            Err(Error::Ws(WsError::HttpFormat(..)))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_selected_protocol() {
+        let url = spawn_server("nostr").await;
+        let opts = ConnectOptions::new().protocols(["chat", "nostr"]);
+
+        let socket = connect(&url, &opts).await.unwrap();
+        assert_eq!(socket.protocol(), Some("nostr"));
+
+        let response: &HandshakeResponse = socket.handshake_response();
+        assert_eq!(response.status(), http::StatusCode::SWITCHING_PROTOCOLS);
+        assert_eq!(response.header("Sec-WebSocket-Protocol"), Some("nostr"));
+        assert_eq!(response.extensions(), None);
+    }
+
+    #[tokio::test]
+    async fn test_unexpected_protocol() {
+        let url = spawn_server("mqtt").await;
+        let opts = ConnectOptions::new().protocols(["chat", "nostr"]);
+
+        assert!(matches!(
+            connect(&url, &opts).await,
+            Err(Error::SubProtocol(SubProtocolError::InvalidSubProtocol))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_no_protocol_selected() {
+        let url = spawn_server("").await;
+        let opts = ConnectOptions::new().protocols(["chat", "nostr"]);
+
+        assert!(matches!(
+            connect(&url, &opts).await,
+            Err(Error::SubProtocol(SubProtocolError::NoSubProtocol))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_connect_to() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let callback = |request: &ServerRequest, response: ServerResponse| {
+                let _ = tx.send(request.headers()["host"].clone());
+                Ok(response)
+            };
+            let _ = tokio_tungstenite::accept_hdr_async(stream, callback).await;
+        });
+
+        // The host doesn't resolve: the connection must go to the explicit address
+        let url = Url::parse(&format!("ws://relay.invalid:{}", addr.port())).unwrap();
+        let opts = ConnectOptions::new().connect_to([addr]);
+
+        assert!(connect(&url, &opts).await.is_ok());
+        assert_eq!(rx.recv().unwrap(), format!("relay.invalid:{}", addr.port()));
+    }
+
+    #[tokio::test]
+    async fn test_static_resolver() {
+        let port: u16 = spawn_server("nostr").await.port().unwrap();
+
+        let url = Url::parse(&format!("ws://relay.invalid:{port}")).unwrap();
+        let resolver = StaticResolver::new().host("relay.invalid", [IpAddr::from([127, 0, 0, 1])]);
+        let opts = ConnectOptions::new()
+            .protocols(["nostr"])
+            .resolver(Arc::new(resolver));
+
+        assert!(connect(&url, &opts).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_http_proxy() {
+        let url = spawn_server("nostr").await;
+        let proxy = spawn_http_proxy("Basic dXNlcjpwYXNz").await;
+        let opts = ConnectOptions::new()
+            .protocols(["nostr"])
+            .mode(ConnectionMode::proxy(
+                Proxy::http(proxy).credentials("user", "pass"),
+            ));
+
+        assert!(connect(&url, &opts).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_proxy_chain() {
+        let url = spawn_server("nostr").await;
+        let first = spawn_http_proxy("Basic dXNlcjpwYXNz").await;
+        let second = spawn_http_proxy("Basic b3RoZXI6cGFzcw==").await;
+        let chain = vec![
+            Proxy::http(first).credentials("user", "pass"),
+            Proxy::http(second).credentials("other", "pass"),
+        ];
+        let opts = ConnectOptions::new()
+            .protocols(["nostr"])
+            .mode(ConnectionMode::proxy_chain(chain));
+
+        assert!(connect(&url, &opts).await.is_ok());
+
+        // The second hop rejects the credentials
+        let first = spawn_http_proxy("Basic dXNlcjpwYXNz").await;
+        let second = spawn_http_proxy("Basic b3RoZXI6cGFzcw==").await;
+        let chain = vec![
+            Proxy::http(first).credentials("user", "pass"),
+            Proxy::http(second).credentials("other", "wrong"),
+        ];
+        let opts = ConnectOptions::new().mode(ConnectionMode::proxy_chain(chain));
+
+        match connect(&url, &opts).await {
+            Err(Error::ProxyHop { hop, error }) => {
+                assert_eq!(hop, 1);
+                assert!(matches!(*error, Error::ProxyConnect { status: 407, .. }));
+            }
+            res => panic!("unexpected result: {:?}", res.err()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_proxy_chain_timeout() {
+        // The second hop accepts the connection but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let second: SocketAddr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (_stream, _) = listener.accept().await.unwrap();
+            std::future::pending::<()>().await;
+        });
+
+        let url = spawn_server("").await;
+        let first = spawn_http_proxy("Basic dXNlcjpwYXNz").await;
+        let chain = vec![
+            Proxy::http(first).credentials("user", "pass"),
+            Proxy::http(second),
+        ];
+        let opts = ConnectOptions::new()
+            .mode(ConnectionMode::proxy_chain(chain))
+            .proxy_timeout(Duration::from_millis(200));
+
+        match connect(&url, &opts).await {
+            Err(Error::ProxyHop { hop, error }) => {
+                assert_eq!(hop, 1);
+                assert!(matches!(*error, Error::Timeout(TimeoutPhase::Proxy)));
+            }
+            res => panic!("unexpected result: {:?}", res.err()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_proxy_chain_max_timeout() {
+        let url = spawn_server("").await;
+        let first = spawn_http_proxy("Basic dXNlcjpwYXNz").await;
+        let second = spawn_http_proxy("Basic b3RoZXI6cGFzcw==").await;
+        let chain = vec![
+            Proxy::http(first).credentials("user", "pass"),
+            Proxy::http(second).credentials("other", "pass"),
+        ];
+        let opts = ConnectOptions::new()
+            .mode(ConnectionMode::proxy_chain(chain))
+            .proxy_timeout(Duration::MAX);
+
+        assert!(connect(&url, &opts).await.is_ok());
+    }
+
+    #[tokio::test]
+    #[cfg(unix)]
+    async fn test_unix() {
+        let dir = std::env::temp_dir().join(format!("async-wsocket-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("ws.sock");
+        let _ = std::fs::remove_file(&path);
+
+        let listener = UnixListener::bind(&path).unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let callback = |request: &ServerRequest, response: ServerResponse| {
+                let _ = tx.send(request.uri().to_string());
+                Ok(response)
+            };
+            let _ = tokio_tungstenite::accept_hdr_async(stream, callback).await;
+        });
+
+        let url = Url::parse("ws://sidecar.invalid/v1/events").unwrap();
+        let opts = ConnectOptions::new().mode(ConnectionMode::unix(&path));
+
+        assert!(connect(&url, &opts).await.is_ok());
+        assert_eq!(rx.recv().unwrap(), "/v1/events");
+
+        // Server-side helper
+        let path = dir.join("accept.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        tokio::spawn(async move {
+            let _ = accept_unix(&listener).await;
+        });
+
+        let opts = ConnectOptions::new().mode(ConnectionMode::unix(&path));
+        assert!(connect(&url, &opts).await.is_ok());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn test_connect_over() {
+        let (client, server) = tokio::io::duplex(4096);
+        tokio::spawn(async move {
+            let _ = tokio_tungstenite::accept_async(server).await;
+        });
+
+        let url = Url::parse("ws://in-process.invalid/path").unwrap();
+        let socket = connect_over(&url, client, &ConnectOptions::new())
+            .await
+            .unwrap();
+        assert_eq!(
+            socket.handshake_response().status(),
+            http::StatusCode::SWITCHING_PROTOCOLS
+        );
+    }
+
+    #[tokio::test]
+    async fn test_custom_connector() {
+        /// In-process transport, recording the requested targets
+        #[derive(Debug, Default)]
+        struct DuplexConnector {
+            targets: std::sync::Mutex<Vec<(String, u16)>>,
+        }
+
+        impl Connector for DuplexConnector {
+            fn connect<'a>(
+                &'a self,
+                host: &'a str,
+                port: u16,
+            ) -> BoxedFuture<'a, io::Result<BoxedStream>> {
+                self.targets.lock().unwrap().push((host.to_string(), port));
+                Box::pin(async {
+                    let (client, server) = tokio::io::duplex(4096);
+                    tokio::spawn(async move {
+                        let _ = tokio_tungstenite::accept_async(server).await;
+                    });
+                    Ok(Box::new(client) as BoxedStream)
+                })
+            }
+        }
+
+        let connector = Arc::new(DuplexConnector::default());
+        let mode = ConnectionMode::custom(connector.clone());
+        assert_eq!(mode, ConnectionMode::Custom(connector.clone()));
+        assert_ne!(
+            mode,
+            ConnectionMode::custom(Arc::new(DuplexConnector::default()))
+        );
+
+        let url = Url::parse("ws://[::1]:8080/path").unwrap();
+        let opts = ConnectOptions::new().mode(mode);
+
+        assert!(connect(&url, &opts).await.is_ok());
+        assert_eq!(
+            *connector.targets.lock().unwrap(),
+            [(String::from("::1"), 8080)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_max_frame_size() {
+        use futures_util::{SinkExt, StreamExt};
+        use tokio_tungstenite::tungstenite::error::CapacityError;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("ws://{}", listener.local_addr().unwrap())).unwrap();
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let config = WebSocketConfig::new().max_frame_size(Some(16));
+            let mut ws = accept_with_config(stream, config).await.unwrap();
+            let _ = tx.send(ws.next().await);
+        });
+
+        let mut socket = connect(&url, &ConnectOptions::new()).await.unwrap();
+        socket
+            .send(crate::Message::Text(String::from("a").repeat(32)))
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            tokio::task::spawn_blocking(move || rx.recv().unwrap())
+                .await
+                .unwrap(),
+            Some(Err(WsError::Capacity(CapacityError::MessageTooLong { .. })))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_timeout() {
+        // Accept the TCP connection but never answer the upgrade request
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = Url::parse(&format!("ws://{}", listener.local_addr().unwrap())).unwrap();
+        tokio::spawn(async move {
+            let (_stream, _) = listener.accept().await.unwrap();
+            std::future::pending::<()>().await;
+        });
+
+        let opts = ConnectOptions::new().handshake_timeout(Duration::from_millis(100));
+
+        assert!(matches!(
+            connect(&url, &opts).await,
+            Err(Error::Timeout(TimeoutPhase::Handshake))
+        ));
+    }
+}
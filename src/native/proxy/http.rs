@@ -0,0 +1,259 @@
+// Copyright (c) 2022-2024 Yuki Kishimoto
+// Distributed under the MIT software license
+
+//! HTTP CONNECT
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio_tungstenite::tungstenite::http::header::{HeaderName, HeaderValue};
+use tokio_tungstenite::tungstenite::Error as WsError;
+
+use super::{ProxyCredentials, ProxyTarget};
+use crate::native::stream::AsyncStream;
+use crate::native::Error;
+
+/// Max size of the response head sent by the proxy
+const MAX_RESPONSE_HEAD: usize = 8 * 1024;
+const MAX_HEADERS: usize = 64;
+
+/// Open a tunnel to `target` with a `CONNECT` request over a stream already connected to the proxy
+///
+/// The returned stream keeps the bytes received after the response head, if any.
+pub(super) async fn connect<S>(
+    mut stream: S,
+    target: &ProxyTarget,
+    headers: &[(String, String)],
+    credentials: Option<&ProxyCredentials>,
+) -> Result<BufReader<S>, Error>
+where
+    S: AsyncStream,
+{
+    let request: String = request(target, headers, credentials)?;
+    stream.write_all(request.as_bytes()).await?;
+    stream.flush().await?;
+
+    let mut stream: BufReader<S> = BufReader::new(stream);
+    let head: Vec<u8> = read_response_head(&mut stream).await?;
+    let (status, reason) = parse_status(&head)?;
+
+    if (200..300).contains(&status) {
+        Ok(stream)
+    } else {
+        Err(Error::ProxyConnect { status, reason })
+    }
+}
+
+/// Build the `CONNECT` request
+fn request(
+    target: &ProxyTarget,
+    headers: &[(String, String)],
+    credentials: Option<&ProxyCredentials>,
+) -> Result<String, Error> {
+    let authority: String = target.to_string();
+
+    let mut request: String = format!("CONNECT {authority} HTTP/1.1\r\nHost: {authority}\r\n");
+
+    if let Some(credentials) = credentials {
+        let token: String =
+            STANDARD.encode(format!("{}:{}", credentials.username, credentials.password));
+        request.push_str(&format!("Proxy-Authorization: Basic {token}\r\n"));
+    }
+
+    for (name, value) in headers.iter() {
+        let name: HeaderName = HeaderName::from_bytes(name.as_bytes()).map_err(WsError::from)?;
+        let value: HeaderValue = HeaderValue::from_str(value).map_err(WsError::from)?;
+        let value: &str = value.to_str().map_err(WsError::from)?;
+        request.push_str(&format!("{name}: {value}\r\n"));
+    }
+
+    request.push_str("\r\n");
+
+    Ok(request)
+}
+
+/// Read the response head, up to the empty line
+///
+/// The bytes of the tunnelled stream received after the head are left in the buffer.
+async fn read_response_head<S>(stream: &mut BufReader<S>) -> Result<Vec<u8>, Error>
+where
+    S: AsyncStream,
+{
+    let mut head: Vec<u8> = Vec::with_capacity(256);
+
+    loop {
+        let buf: &[u8] = stream.fill_buf().await?;
+        if buf.is_empty() {
+            return Err(Error::Io(std::io::ErrorKind::UnexpectedEof.into()));
+        }
+
+        // The end of the head may be split across two reads
+        let start: usize = head.len().saturating_sub(3);
+        let read: usize = buf.len();
+        head.extend_from_slice(buf);
+
+        match head[start..].windows(4).position(|w| w == b"\r\n\r\n") {
+            Some(pos) => {
+                let end: usize = start + pos + 4;
+                stream.consume(read - (head.len() - end));
+                head.truncate(end);
+                return Ok(head);
+            }
+            None => {
+                stream.consume(read);
+                if head.len() >= MAX_RESPONSE_HEAD {
+                    return Err(invalid_response("response head too large"));
+                }
+            }
+        }
+    }
+}
+
+/// Parse the status code and the reason phrase
+fn parse_status(head: &[u8]) -> Result<(u16, String), Error> {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut response = httparse::Response::new(&mut headers);
+
+    match response.parse(head) {
+        Ok(httparse::Status::Complete(..)) => match response.code {
+            Some(status) => Ok((status, response.reason.unwrap_or_default().to_string())),
+            None => Err(invalid_response("missing status code")),
+        },
+        Ok(httparse::Status::Partial) => Err(invalid_response("incomplete response")),
+        Err(e) => Err(invalid_response(&e.to_string())),
+    }
+}
+
+#[inline]
+fn invalid_response(msg: &str) -> Error {
+    Error::Io(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("invalid proxy response: {msg}"),
+    ))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::net::SocketAddr;
+
+    use tokio::io::AsyncReadExt;
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+    use crate::native::tcp::tests::spawn_echo_server;
+
+    /// Spawn a CONNECT proxy that requires the given `Proxy-Authorization` header
+    pub(crate) async fn spawn_http_proxy(authorization: &'static str) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+
+            let head: Vec<u8> = read_response_head(&mut stream).await.unwrap();
+            let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+            let mut request = httparse::Request::new(&mut headers);
+            request.parse(&head).unwrap();
+            assert_eq!(request.method, Some("CONNECT"));
+
+            let authorized: bool = request.headers.iter().any(|header| {
+                header.name.eq_ignore_ascii_case("proxy-authorization")
+                    && header.value == authorization.as_bytes()
+            });
+            if !authorized {
+                stream
+                    .write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")
+                    .await
+                    .unwrap();
+                return;
+            }
+
+            let target: &str = request.path.unwrap();
+            let mut upstream = TcpStream::connect(target).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                .await
+                .unwrap();
+
+            let _ = tokio::io::copy_bidirectional(&mut stream, &mut upstream).await;
+        });
+
+        addr
+    }
+
+    #[test]
+    fn test_request() {
+        let target = ProxyTarget::Domain(String::from("relay.example.com"), 443);
+        let headers = [(String::from("X-Client"), String::from("test"))];
+        let credentials = ProxyCredentials::new("user", "pass");
+
+        assert_eq!(
+            request(&target, &headers, Some(&credentials)).unwrap(),
+            "CONNECT relay.example.com:443 HTTP/1.1\r\n\
+             Host: relay.example.com:443\r\n\
+             Proxy-Authorization: Basic dXNlcjpwYXNz\r\n\
+             x-client: test\r\n\r\n"
+        );
+
+        let target = ProxyTarget::Ip("[::1]:8080".parse().unwrap());
+        assert!(request(&target, &[], None)
+            .unwrap()
+            .starts_with("CONNECT [::1]:8080 HTTP/1.1\r\n"));
+
+        let headers = [(String::from("Invalid Name"), String::from("value"))];
+        assert!(request(&target, &headers, None).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_connect() {
+        let target = ProxyTarget::Ip(spawn_echo_server().await);
+        let credentials = ProxyCredentials::new("user", "pass");
+
+        let proxy: SocketAddr = spawn_http_proxy("Basic dXNlcjpwYXNz").await;
+        let socket = TcpStream::connect(proxy).await.unwrap();
+        let mut stream = connect(socket, &target, &[], Some(&credentials))
+            .await
+            .unwrap();
+
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        // Missing credentials
+        let proxy: SocketAddr = spawn_http_proxy("Basic dXNlcjpwYXNz").await;
+        let socket = TcpStream::connect(proxy).await.unwrap();
+        match connect(socket, &target, &[], None).await {
+            Err(Error::ProxyConnect { status, reason }) => {
+                assert_eq!(status, 407);
+                assert_eq!(reason, "Proxy Authentication Required");
+            }
+            res => panic!("unexpected result: {:?}", res.err()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connect_early_data() {
+        // The proxy sends the first bytes of the tunnel with the response head
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy: SocketAddr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            read_response_head(&mut stream).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\nhello")
+                .await
+                .unwrap();
+        });
+
+        let target = ProxyTarget::Domain(String::from("relay.example.com"), 443);
+        let socket = TcpStream::connect(proxy).await.unwrap();
+        let mut stream = connect(socket, &target, &[], None).await.unwrap();
+
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+    }
+}
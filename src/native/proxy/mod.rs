@@ -0,0 +1,573 @@
+// Copyright (c) 2022-2024 Yuki Kishimoto
+// Distributed under the MIT software license
+
+//! Proxy
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::{fmt, io};
+
+use tokio::net::TcpStream;
+use tokio::time::Instant;
+use url::{Host, Url};
+
+mod config;
+pub(crate) mod http;
+#[cfg(feature = "socks")]
+mod socks;
+#[cfg(feature = "tor")]
+mod tor;
+
+pub(crate) use self::config::from_env;
+#[cfg(feature = "tor")]
+pub use self::tor::{Tor, TorAuth, TorControl};
+use super::stream::BoxedStream;
+use super::{resolve_host, tcp, timeout, tls, Error};
+use crate::{ConnectOptions, TimeoutPhase};
+
+/// Proxy config
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Proxy {
+    pub(crate) protocol: ProxyProtocol,
+    pub(crate) host: Host<String>,
+    pub(crate) port: u16,
+    pub(crate) credentials: Option<ProxyCredentials>,
+    pub(crate) isolate: bool,
+    pub(crate) dns: ProxyDns,
+    pub(crate) headers: Vec<(String, String)>,
+}
+
+/// Proxy protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ProxyProtocol {
+    /// SOCKS5
+    #[cfg(feature = "socks")]
+    Socks5,
+    /// SOCKS4 (IPv4 targets only)
+    #[cfg(feature = "socks")]
+    Socks4,
+    /// SOCKS4a (IPv4 and domain targets)
+    #[cfg(feature = "socks")]
+    Socks4a,
+    /// HTTP CONNECT
+    Http,
+    /// HTTP CONNECT, over TLS to the proxy
+    Https,
+}
+
+impl fmt::Display for ProxyProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "socks")]
+            Self::Socks5 => write!(f, "SOCKS5"),
+            #[cfg(feature = "socks")]
+            Self::Socks4 => write!(f, "SOCKS4"),
+            #[cfg(feature = "socks")]
+            Self::Socks4a => write!(f, "SOCKS4a"),
+            Self::Http => write!(f, "HTTP"),
+            Self::Https => write!(f, "HTTPS"),
+        }
+    }
+}
+
+/// Where the target host is resolved when connecting through a proxy
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ProxyDns {
+    /// The proxy resolves the host (i.e., `socks5h`), avoiding local DNS leaks
+    #[default]
+    Remote,
+    /// The host is resolved locally, through the configured [`Resolver`](crate::native::Resolver),
+    /// and the proxy receives the IP address
+    Local,
+    /// Only IP addresses are sent to the proxy: hosts that are not IP addresses are rejected,
+    /// unless explicit addresses are set with [`ConnectOptions::connect_to`](crate::ConnectOptions::connect_to)
+    IpOnly,
+}
+
+impl fmt::Display for ProxyDns {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Remote => write!(f, "remote"),
+            Self::Local => write!(f, "local"),
+            Self::IpOnly => write!(f, "IP only"),
+        }
+    }
+}
+
+#[cfg(feature = "socks")]
+impl From<SocketAddr> for Proxy {
+    #[inline]
+    fn from(addr: SocketAddr) -> Self {
+        Self::socks5(addr)
+    }
+}
+
+impl Proxy {
+    /// SOCKS5 proxy
+    #[inline]
+    #[cfg(feature = "socks")]
+    pub fn socks5(addr: SocketAddr) -> Self {
+        Self::new(ProxyProtocol::Socks5, addr, ProxyDns::Remote)
+    }
+
+    /// SOCKS4 proxy
+    ///
+    /// SOCKS4 doesn't support domain targets, so the host is resolved locally.
+    #[inline]
+    #[cfg(feature = "socks")]
+    pub fn socks4(addr: SocketAddr) -> Self {
+        Self::new(ProxyProtocol::Socks4, addr, ProxyDns::Local)
+    }
+
+    /// SOCKS4a proxy
+    #[inline]
+    #[cfg(feature = "socks")]
+    pub fn socks4a(addr: SocketAddr) -> Self {
+        Self::new(ProxyProtocol::Socks4a, addr, ProxyDns::Remote)
+    }
+
+    /// HTTP CONNECT proxy
+    #[inline]
+    pub fn http(addr: SocketAddr) -> Self {
+        Self::new(ProxyProtocol::Http, addr, ProxyDns::Remote)
+    }
+
+    /// HTTP CONNECT proxy, connecting to the proxy over TLS
+    ///
+    /// The TLS config can be set with [`ConnectOptions::proxy_tls`].
+    #[inline]
+    pub fn https(addr: SocketAddr) -> Self {
+        Self::new(ProxyProtocol::Https, addr, ProxyDns::Remote)
+    }
+
+    #[inline]
+    fn new(protocol: ProxyProtocol, addr: SocketAddr, dns: ProxyDns) -> Self {
+        let host: Host<String> = match addr.ip() {
+            IpAddr::V4(ip) => Host::Ipv4(ip),
+            IpAddr::V6(ip) => Host::Ipv6(ip),
+        };
+        Self::with_host(protocol, host, addr.port(), dns)
+    }
+
+    fn with_host(protocol: ProxyProtocol, host: Host<String>, port: u16, dns: ProxyDns) -> Self {
+        Self {
+            protocol,
+            host,
+            port,
+            credentials: None,
+            isolate: false,
+            dns,
+            headers: Vec::new(),
+        }
+    }
+
+    /// Authenticate with username and password
+    ///
+    /// HTTP proxies use the `Basic` scheme. SOCKS4 only supports the user ID: the password is ignored.
+    #[inline]
+    pub fn credentials<U, P>(mut self, username: U, password: P) -> Self
+    where
+        U: Into<String>,
+        P: Into<String>,
+    {
+        self.credentials = Some(ProxyCredentials::new(username, password));
+        self
+    }
+
+    /// Use unique random credentials for every connection (default: false)
+    ///
+    /// Tor isolates the streams with different SOCKS credentials (`IsolateSOCKSAuth`),
+    /// so every connection uses a different circuit. Takes precedence over [`Proxy::credentials`].
+    #[inline]
+    pub fn isolate_streams(mut self, isolate: bool) -> Self {
+        self.isolate = isolate;
+        self
+    }
+
+    /// Set where the target host is resolved
+    ///
+    /// Default: [`ProxyDns::Remote`], or [`ProxyDns::Local`] for SOCKS4.
+    #[inline]
+    pub fn dns(mut self, dns: ProxyDns) -> Self {
+        self.dns = dns;
+        self
+    }
+
+    /// Add a header to the CONNECT request (HTTP proxies only)
+    #[inline]
+    pub fn header<K, V>(mut self, name: K, value: V) -> Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    #[inline]
+    fn host(&self) -> Host<&str> {
+        match &self.host {
+            Host::Domain(domain) => Host::Domain(domain.as_str()),
+            Host::Ipv4(ip) => Host::Ipv4(*ip),
+            Host::Ipv6(ip) => Host::Ipv6(*ip),
+        }
+    }
+
+    /// Get the credentials to use for a new connection
+    pub(crate) fn connection_credentials(&self) -> Option<ProxyCredentials> {
+        if self.isolate {
+            Some(ProxyCredentials::random())
+        } else {
+            self.credentials.clone()
+        }
+    }
+}
+
+/// Proxy username and password
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ProxyCredentials {
+    pub(crate) username: String,
+    pub(crate) password: String,
+}
+
+impl fmt::Debug for ProxyCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never print the password
+        f.debug_struct("ProxyCredentials")
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ProxyCredentials {
+    /// New credentials
+    pub fn new<U, P>(username: U, password: P) -> Self
+    where
+        U: Into<String>,
+        P: Into<String>,
+    {
+        Self {
+            username: username.into(),
+            password: password.into(),
+        }
+    }
+
+    /// Unique random credentials, used for Tor stream isolation
+    fn random() -> Self {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+        let token: String = format!("{:016x}", hasher.finish());
+
+        Self {
+            username: token.clone(),
+            password: token,
+        }
+    }
+}
+
+/// Target address sent to the proxy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ProxyTarget {
+    Ip(SocketAddr),
+    Domain(String, u16),
+}
+
+impl fmt::Display for ProxyTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ip(addr) => write!(f, "{addr}"),
+            Self::Domain(domain, port) => write!(f, "{domain}:{port}"),
+        }
+    }
+}
+
+/// Connect to the proxy and open a tunnel to the URL host
+pub(super) async fn connect(
+    url: &Url,
+    opts: &ConnectOptions,
+    proxy: &Proxy,
+) -> Result<BoxedStream, Error> {
+    timeout(opts.proxy_timeout, TimeoutPhase::Proxy, async {
+        let stream: BoxedStream = connect_tcp(proxy, opts).await?;
+        let target: ProxyTarget = target(url, opts, proxy).await?;
+        handshake(stream, &target, proxy, opts).await
+    })
+    .await
+}
+
+/// Connect to the first proxy of the chain and open a tunnel through every hop to the URL host
+///
+/// Every hop tunnels to the next one through the previous hops.
+/// The proxy timeout covers the whole chain, but is reported for the hop that was in progress.
+pub(super) async fn connect_chain(
+    url: &Url,
+    opts: &ConnectOptions,
+    chain: &[Proxy],
+) -> Result<BoxedStream, Error> {
+    // A timeout too large to be represented is treated as no timeout
+    let deadline: Option<Instant> = opts
+        .proxy_timeout
+        .and_then(|timeout| Instant::now().checked_add(timeout));
+    let mut stream: Option<BoxedStream> = None;
+
+    for (hop, proxy) in chain.iter().enumerate() {
+        let previous: Option<BoxedStream> = stream.take();
+        let next: Option<&Proxy> = chain.get(hop + 1);
+
+        let tunnel = async {
+            let stream: BoxedStream = match previous {
+                Some(stream) => stream,
+                None => connect_tcp(proxy, opts).await?,
+            };
+
+            let target: ProxyTarget = match next {
+                Some(next) => host_target(next.host(), next.port, opts, proxy).await?,
+                None => target(url, opts, proxy).await?,
+            };
+
+            handshake(stream, &target, proxy, opts).await
+        };
+
+        let res: Result<BoxedStream, Error> = match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, tunnel)
+                .await
+                .unwrap_or(Err(Error::Timeout(TimeoutPhase::Proxy))),
+            None => tunnel.await,
+        };
+
+        stream = Some(res.map_err(|e| Error::ProxyHop {
+            hop,
+            error: Box::new(e),
+        })?);
+    }
+
+    stream.ok_or_else(|| {
+        Error::Io(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "empty proxy chain",
+        ))
+    })
+}
+
+/// Open the TCP connection to the proxy
+async fn connect_tcp(proxy: &Proxy, opts: &ConnectOptions) -> Result<BoxedStream, Error> {
+    let addrs: Vec<SocketAddr> = resolve_host(proxy.host(), proxy.port, opts).await?;
+    let stream: TcpStream = tcp::connect(&addrs, opts).await?;
+    Ok(Box::new(stream))
+}
+
+/// Open a tunnel to the target over a stream connected to the proxy
+async fn handshake(
+    stream: BoxedStream,
+    target: &ProxyTarget,
+    proxy: &Proxy,
+    opts: &ConnectOptions,
+) -> Result<BoxedStream, Error> {
+    let credentials: Option<ProxyCredentials> = proxy.connection_credentials();
+    let credentials: Option<&ProxyCredentials> = credentials.as_ref();
+
+    match proxy.protocol {
+        #[cfg(feature = "socks")]
+        ProxyProtocol::Socks5 => Ok(Box::new(
+            socks::connect_socks5(stream, target, credentials).await?,
+        )),
+        #[cfg(feature = "socks")]
+        ProxyProtocol::Socks4 | ProxyProtocol::Socks4a => Ok(Box::new(
+            socks::connect_socks4(stream, target, credentials).await?,
+        )),
+        ProxyProtocol::Http => Ok(Box::new(
+            http::connect(stream, target, &proxy.headers, credentials).await?,
+        )),
+        ProxyProtocol::Https => {
+            let domain: String = tls::host_name(proxy.host());
+            let stream = tls::connect(&domain, stream, &opts.proxy_tls).await?;
+            Ok(Box::new(
+                http::connect(stream, target, &proxy.headers, credentials).await?,
+            ))
+        }
+    }
+}
+
+/// Get the target address to send to the proxy, according to the DNS resolution mode
+async fn target(url: &Url, opts: &ConnectOptions, proxy: &Proxy) -> Result<ProxyTarget, Error> {
+    if !opts.connect_to.is_empty() {
+        return ip_target(&opts.connect_to, proxy).ok_or_else(|| ipv4_only(&opts.connect_to[0]));
+    }
+
+    let host: Host<&str> = url.host().ok_or_else(Error::empty_host)?;
+    let port: u16 = url
+        .port_or_known_default()
+        .ok_or_else(Error::invalid_port)?;
+
+    host_target(host, port, opts, proxy).await
+}
+
+/// Get the target address to send to the proxy for the host
+async fn host_target(
+    host: Host<&str>,
+    port: u16,
+    opts: &ConnectOptions,
+    proxy: &Proxy,
+) -> Result<ProxyTarget, Error> {
+    match host {
+        Host::Domain(domain) => match proxy.dns {
+            // SOCKS4 doesn't support domain targets
+            #[cfg(feature = "socks")]
+            ProxyDns::Remote if proxy.protocol == ProxyProtocol::Socks4 => Err(Error::ProxyDns {
+                host: domain.to_string(),
+                dns: proxy.dns,
+            }),
+            ProxyDns::Remote => Ok(ProxyTarget::Domain(domain.to_string(), port)),
+            ProxyDns::Local => {
+                let addrs: Vec<SocketAddr> = resolve_host(host, port, opts).await?;
+                let addrs: Vec<SocketAddr> = tcp::sort_addrs(&addrs, opts);
+
+                ip_target(&addrs, proxy).ok_or_else(|| {
+                    Error::Io(io::Error::new(
+                        io::ErrorKind::AddrNotAvailable,
+                        format!("'{domain}' resolved to no usable address"),
+                    ))
+                })
+            }
+            ProxyDns::IpOnly => Err(Error::ProxyDns {
+                host: domain.to_string(),
+                dns: proxy.dns,
+            }),
+        },
+        Host::Ipv4(ip) => Ok(ProxyTarget::Ip(SocketAddr::new(IpAddr::V4(ip), port))),
+        Host::Ipv6(ip) => {
+            let addr: SocketAddr = SocketAddr::new(IpAddr::V6(ip), port);
+            ip_target(&[addr], proxy).ok_or_else(|| ipv4_only(&addr))
+        }
+    }
+}
+
+/// Pick the first address the proxy can tunnel to
+///
+/// SOCKS4 only supports IPv4 targets.
+fn ip_target(addrs: &[SocketAddr], proxy: &Proxy) -> Option<ProxyTarget> {
+    let addr: Option<&SocketAddr> = match proxy.protocol {
+        #[cfg(feature = "socks")]
+        ProxyProtocol::Socks4 | ProxyProtocol::Socks4a => addrs.iter().find(|addr| addr.is_ipv4()),
+        _ => addrs.first(),
+    };
+    addr.map(|addr| ProxyTarget::Ip(*addr))
+}
+
+#[inline]
+fn ipv4_only(addr: &SocketAddr) -> Error {
+    Error::Io(io::Error::new(
+        io::ErrorKind::AddrNotAvailable,
+        format!("SOCKS4 only supports IPv4 targets, can't connect to {addr}"),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::native::StaticResolver;
+
+    #[tokio::test]
+    async fn test_target() {
+        let url = Url::parse("wss://relay.example.com").unwrap();
+        let ip = IpAddr::from([10, 0, 0, 1]);
+        let proxy = Proxy::http(SocketAddr::from(([127, 0, 0, 1], 8080)));
+
+        assert_eq!(
+            target(&url, &ConnectOptions::new(), &proxy).await.unwrap(),
+            ProxyTarget::Domain(String::from("relay.example.com"), 443)
+        );
+
+        let resolver = StaticResolver::new().host("relay.example.com", [ip]);
+        let opts = ConnectOptions::new().resolver(Arc::new(resolver));
+        assert_eq!(
+            target(&url, &opts, &proxy.clone().dns(ProxyDns::Local))
+                .await
+                .unwrap(),
+            ProxyTarget::Ip(SocketAddr::new(ip, 443))
+        );
+
+        assert!(matches!(
+            target(&url, &opts, &proxy.clone().dns(ProxyDns::IpOnly)).await,
+            Err(Error::ProxyDns { .. })
+        ));
+
+        let url = Url::parse("ws://10.0.0.1:8080").unwrap();
+        assert_eq!(
+            target(&url, &opts, &proxy.dns(ProxyDns::IpOnly))
+                .await
+                .unwrap(),
+            ProxyTarget::Ip(SocketAddr::new(ip, 8080))
+        );
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "socks")]
+    async fn test_socks4_target() {
+        let url = Url::parse("wss://relay.example.com").unwrap();
+        let ip = IpAddr::from([10, 0, 0, 1]);
+        let addr = SocketAddr::from(([127, 0, 0, 1], 1080));
+
+        let resolver = StaticResolver::new().host("relay.example.com", [ip]);
+        let opts = ConnectOptions::new().resolver(Arc::new(resolver));
+
+        // SOCKS4 resolves locally by default and doesn't support domain targets
+        let socks4 = Proxy::socks4(addr);
+        assert_eq!(
+            target(&url, &opts, &socks4).await.unwrap(),
+            ProxyTarget::Ip(SocketAddr::new(ip, 443))
+        );
+        assert!(matches!(
+            target(&url, &opts, &socks4.clone().dns(ProxyDns::Remote)).await,
+            Err(Error::ProxyDns { .. })
+        ));
+        assert_eq!(
+            target(&url, &opts, &Proxy::socks4a(addr)).await.unwrap(),
+            ProxyTarget::Domain(String::from("relay.example.com"), 443)
+        );
+
+        // IPv6 targets
+        let ipv6: SocketAddr = "[::1]:443".parse().unwrap();
+        let opts = ConnectOptions::new().connect_to([ipv6]);
+        match target(&url, &opts, &socks4).await {
+            Err(Error::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::AddrNotAvailable),
+            res => panic!("unexpected result: {res:?}"),
+        }
+        let ipv4 = SocketAddr::new(ip, 443);
+        let opts = ConnectOptions::new().connect_to([ipv6, ipv4]);
+        assert_eq!(
+            target(&url, &opts, &socks4).await.unwrap(),
+            ProxyTarget::Ip(ipv4)
+        );
+        let url = Url::parse("wss://[::1]").unwrap();
+        assert!(matches!(
+            target(&url, &ConnectOptions::new(), &Proxy::socks4a(addr)).await,
+            Err(Error::Io(..))
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "socks")]
+    fn test_stream_isolation() {
+        let addr: SocketAddr = "127.0.0.1:9050".parse().unwrap();
+        let proxy = Proxy::socks5(addr).credentials("user", "pass");
+        assert_eq!(
+            proxy.connection_credentials(),
+            Some(ProxyCredentials::new("user", "pass"))
+        );
+
+        let proxy = proxy.isolate_streams(true);
+        let a = proxy.connection_credentials().unwrap();
+        let b = proxy.connection_credentials().unwrap();
+        assert_ne!(a, b);
+
+        let credentials = ProxyCredentials::new("user", "secret");
+        assert!(!format!("{credentials:?}").contains("secret"));
+    }
+}
@@ -0,0 +1,200 @@
+// Copyright (c) 2022-2024 Yuki Kishimoto
+// Distributed under the MIT software license
+
+//! Socks
+
+use tokio_socks::tcp::{Socks4Stream, Socks5Stream};
+use tokio_socks::TargetAddr;
+
+use super::{ProxyCredentials, ProxyTarget};
+use crate::native::stream::AsyncStream;
+
+impl ProxyTarget {
+    fn to_target_addr(&self) -> TargetAddr<'_> {
+        match self {
+            Self::Ip(addr) => TargetAddr::Ip(*addr),
+            Self::Domain(domain, port) => TargetAddr::Domain(domain.as_str().into(), *port),
+        }
+    }
+}
+
+/// Negotiate the SOCKS4 connection to `target` over a stream already connected to the proxy
+///
+/// Domain targets are sent using the SOCKS4a extension.
+pub(super) async fn connect_socks4<S>(
+    socket: S,
+    target: &ProxyTarget,
+    credentials: Option<&ProxyCredentials>,
+) -> Result<S, tokio_socks::Error>
+where
+    S: AsyncStream,
+{
+    let dest: TargetAddr<'_> = target.to_target_addr();
+    let stream = match credentials {
+        Some(credentials) => {
+            Socks4Stream::connect_with_userid_and_socket(socket, dest, &credentials.username)
+                .await?
+        }
+        None => Socks4Stream::connect_with_socket(socket, dest).await?,
+    };
+    Ok(stream.into_inner())
+}
+
+/// Negotiate the SOCKS5 connection to `target` over a stream already connected to the proxy
+pub(super) async fn connect_socks5<S>(
+    socket: S,
+    target: &ProxyTarget,
+    credentials: Option<&ProxyCredentials>,
+) -> Result<S, tokio_socks::Error>
+where
+    S: AsyncStream,
+{
+    let dest: TargetAddr<'_> = target.to_target_addr();
+    let stream = match credentials {
+        Some(credentials) => {
+            Socks5Stream::connect_with_password_and_socket(
+                socket,
+                dest,
+                &credentials.username,
+                &credentials.password,
+            )
+            .await?
+        }
+        None => Socks5Stream::connect_with_socket(socket, dest).await?,
+    };
+    Ok(stream.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use super::*;
+    use crate::native::tcp::tests::spawn_echo_server;
+
+    /// Spawn a SOCKS5 server that requires the given credentials
+    async fn spawn_socks5_server(username: &'static str, password: &'static str) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            // Greeting: select username/password auth
+            let mut buf = [0u8; 2];
+            stream.read_exact(&mut buf).await.unwrap();
+            let mut methods = vec![0u8; buf[1] as usize];
+            stream.read_exact(&mut methods).await.unwrap();
+            stream.write_all(&[0x05, 0x02]).await.unwrap();
+
+            // Auth request
+            let mut buf = [0u8; 2];
+            stream.read_exact(&mut buf).await.unwrap();
+            let mut user = vec![0u8; buf[1] as usize];
+            stream.read_exact(&mut user).await.unwrap();
+            let len = stream.read_u8().await.unwrap();
+            let mut pass = vec![0u8; len as usize];
+            stream.read_exact(&mut pass).await.unwrap();
+
+            if user != username.as_bytes() || pass != password.as_bytes() {
+                stream.write_all(&[0x01, 0x01]).await.unwrap();
+                return;
+            }
+            stream.write_all(&[0x01, 0x00]).await.unwrap();
+
+            // Connect request (IPv4 target only)
+            let mut buf = [0u8; 10];
+            stream.read_exact(&mut buf).await.unwrap();
+            let target = SocketAddr::from((
+                [buf[4], buf[5], buf[6], buf[7]],
+                u16::from_be_bytes([buf[8], buf[9]]),
+            ));
+            let mut upstream = TcpStream::connect(target).await.unwrap();
+            stream
+                .write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+
+            let _ = tokio::io::copy_bidirectional(&mut stream, &mut upstream).await;
+        });
+
+        addr
+    }
+
+    /// Spawn a SOCKS4 server, only accepting IPv4 targets
+    async fn spawn_socks4_server() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            let mut buf = [0u8; 8];
+            stream.read_exact(&mut buf).await.unwrap();
+            let target = SocketAddr::from((
+                [buf[4], buf[5], buf[6], buf[7]],
+                u16::from_be_bytes([buf[2], buf[3]]),
+            ));
+
+            // User ID
+            while stream.read_u8().await.unwrap() != 0 {}
+
+            let mut upstream = TcpStream::connect(target).await.unwrap();
+            stream
+                .write_all(&[0x00, 0x5a, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+
+            let _ = tokio::io::copy_bidirectional(&mut stream, &mut upstream).await;
+        });
+
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_socks4() {
+        let target: SocketAddr = spawn_echo_server().await;
+        let proxy: SocketAddr = spawn_socks4_server().await;
+
+        let socket = TcpStream::connect(proxy).await.unwrap();
+        let credentials = ProxyCredentials::new("user", "");
+        let mut stream = connect_socks4(socket, &ProxyTarget::Ip(target), Some(&credentials))
+            .await
+            .unwrap();
+
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+    }
+
+    #[tokio::test]
+    async fn test_socks5_credentials() {
+        let target: SocketAddr = spawn_echo_server().await;
+
+        // Valid credentials
+        let proxy: SocketAddr = spawn_socks5_server("user", "pass").await;
+        let socket = TcpStream::connect(proxy).await.unwrap();
+        let credentials = ProxyCredentials::new("user", "pass");
+        let mut stream = connect_socks5(socket, &ProxyTarget::Ip(target), Some(&credentials))
+            .await
+            .unwrap();
+
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        // Invalid credentials
+        let proxy: SocketAddr = spawn_socks5_server("user", "pass").await;
+        let socket = TcpStream::connect(proxy).await.unwrap();
+        let credentials = ProxyCredentials::new("user", "wrong");
+        assert!(matches!(
+            connect_socks5(socket, &ProxyTarget::Ip(target), Some(&credentials)).await,
+            Err(tokio_socks::Error::PasswordAuthFailure(..))
+        ));
+    }
+}
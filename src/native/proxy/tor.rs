@@ -0,0 +1,484 @@
+// Copyright (c) 2022-2024 Yuki Kishimoto
+// Distributed under the MIT software license
+
+//! Tor
+
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use url::Url;
+
+use super::{Proxy, ProxyCredentials};
+use crate::native::Error;
+
+const DEFAULT_CONTROL_TIMEOUT: Duration = Duration::from_secs(10);
+/// Max length of a line sent by the control port
+const MAX_LINE_LENGTH: u64 = 64 * 1024;
+
+/// Tor client, connecting through the SOCKS port with a circuit per relay
+///
+/// Every host gets its own isolation credentials, so Tor uses a different circuit for each one.
+/// Cloning shares the circuits.
+#[derive(Debug, Clone)]
+pub struct Tor {
+    socks: SocketAddr,
+    control: Option<SocketAddr>,
+    auth: TorAuth,
+    control_timeout: Duration,
+    circuits: Arc<Mutex<HashMap<String, ProxyCredentials>>>,
+}
+
+impl Tor {
+    /// New Tor client using the SOCKS port (i.e., `127.0.0.1:9050`)
+    pub fn new(socks: SocketAddr) -> Self {
+        Self {
+            socks,
+            control: None,
+            auth: TorAuth::None,
+            control_timeout: DEFAULT_CONTROL_TIMEOUT,
+            circuits: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Set the control port (i.e., `127.0.0.1:9051`), required for [`Tor::new_identity`]
+    #[inline]
+    pub fn control_port(mut self, addr: SocketAddr, auth: TorAuth) -> Self {
+        self.control = Some(addr);
+        self.auth = auth;
+        self
+    }
+
+    /// Set the timeout for connecting and authenticating to the control port (default: 10 secs)
+    #[inline]
+    pub fn control_timeout(mut self, timeout: Duration) -> Self {
+        self.control_timeout = timeout;
+        self
+    }
+
+    /// Get the proxy to use for the URL
+    ///
+    /// The host is resolved by Tor.
+    /// Fails if the URL has no host.
+    pub fn proxy(&self, url: &Url) -> Result<Proxy, Error> {
+        let host: String = circuit_key(url)?;
+
+        let mut circuits = self.circuits.lock().unwrap_or_else(|e| e.into_inner());
+        let credentials: ProxyCredentials = circuits
+            .entry(host)
+            .or_insert_with(ProxyCredentials::random)
+            .clone();
+
+        let mut proxy: Proxy = Proxy::socks5(self.socks);
+        proxy.credentials = Some(credentials);
+        Ok(proxy)
+    }
+
+    /// Use a new circuit for the next connections to the URL host
+    ///
+    /// The open connections aren't affected.
+    /// Fails if the URL has no host.
+    pub fn renew_circuit(&self, url: &Url) -> Result<(), Error> {
+        let host: String = circuit_key(url)?;
+        let mut circuits = self.circuits.lock().unwrap_or_else(|e| e.into_inner());
+        circuits.remove(&host);
+        Ok(())
+    }
+
+    /// Send `SIGNAL NEWNYM` through the control port and use new circuits for all the next connections
+    pub async fn new_identity(&self) -> Result<(), Error> {
+        let mut control: TorControl = self.control().await?;
+        control.signal_newnym().await?;
+
+        let mut circuits = self.circuits.lock().unwrap_or_else(|e| e.into_inner());
+        circuits.clear();
+
+        Ok(())
+    }
+
+    /// Open an authenticated connection to the control port
+    pub async fn control(&self) -> Result<TorControl, Error> {
+        match self.control {
+            Some(addr) => TorControl::connect(addr, &self.auth, self.control_timeout).await,
+            None => Err(Error::TorControl {
+                code: None,
+                message: String::from("control port not set"),
+            }),
+        }
+    }
+}
+
+/// Tor control port authentication
+#[derive(Clone, Default)]
+pub enum TorAuth {
+    /// No authentication
+    #[default]
+    None,
+    /// Password (`HashedControlPassword`)
+    Password(String),
+    /// Cookie file (`CookieAuthentication`)
+    Cookie(PathBuf),
+}
+
+impl fmt::Debug for TorAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => write!(f, "None"),
+            // Never print the password
+            Self::Password(..) => f.debug_tuple("Password").finish_non_exhaustive(),
+            Self::Cookie(path) => f.debug_tuple("Cookie").field(path).finish(),
+        }
+    }
+}
+
+/// Tor control protocol client
+#[derive(Debug)]
+pub struct TorControl {
+    stream: BufReader<TcpStream>,
+}
+
+/// Reply line: status code, line and optional data block
+type ReplyLine = (u16, String, Option<String>);
+
+impl TorControl {
+    /// Connect to the control port and authenticate
+    ///
+    /// Fails with [`Error::TorControl`] if it takes longer than `timeout`.
+    pub async fn connect(
+        addr: SocketAddr,
+        auth: &TorAuth,
+        timeout: Duration,
+    ) -> Result<Self, Error> {
+        let future = async {
+            let command: String = match auth {
+                TorAuth::None => String::from("AUTHENTICATE"),
+                TorAuth::Password(password) => format!("AUTHENTICATE {}", quote(password)?),
+                TorAuth::Cookie(path) => {
+                    let cookie: Vec<u8> = tokio::fs::read(path).await?;
+                    format!("AUTHENTICATE {}", hex(&cookie))
+                }
+            };
+
+            let stream: TcpStream = TcpStream::connect(addr).await?;
+            let mut control: Self = Self {
+                stream: BufReader::new(stream),
+            };
+            control.command(&command).await?;
+
+            Ok(control)
+        };
+
+        tokio::time::timeout(timeout, future)
+            .await
+            .map_err(|_| Error::TorControl {
+                code: None,
+                message: format!("no authentication reply within {timeout:?}"),
+            })?
+    }
+
+    /// Ask Tor to use new circuits for the next connections (`SIGNAL NEWNYM`)
+    ///
+    /// Tor may rate-limit the signal.
+    #[inline]
+    pub async fn signal_newnym(&mut self) -> Result<(), Error> {
+        self.command("SIGNAL NEWNYM").await?;
+        Ok(())
+    }
+
+    /// Get the value of a key (`GETINFO`), i.e., `version` or `status/circuit-established`
+    pub async fn get_info(&mut self, key: &str) -> Result<String, Error> {
+        check_line(key)?;
+        let reply: Vec<ReplyLine> = self.command(&format!("GETINFO {key}")).await?;
+
+        let prefix: String = format!("{key}=");
+        reply
+            .into_iter()
+            .find_map(|(_, line, data)| {
+                let value: &str = line.strip_prefix(&prefix)?;
+                Some(data.unwrap_or_else(|| value.to_string()))
+            })
+            .ok_or_else(|| Error::TorControl {
+                code: None,
+                message: format!("missing '{key}' in the reply"),
+            })
+    }
+
+    /// Send the command and read the reply, failing if the status isn't `250`
+    async fn command(&mut self, command: &str) -> Result<Vec<ReplyLine>, Error> {
+        let stream: &mut TcpStream = self.stream.get_mut();
+        stream
+            .write_all(format!("{command}\r\n").as_bytes())
+            .await?;
+        stream.flush().await?;
+
+        let reply: Vec<ReplyLine> = self.read_reply().await?;
+
+        match reply.last() {
+            Some((250, ..)) => Ok(reply),
+            Some((code, line, ..)) => Err(Error::TorControl {
+                code: Some(*code),
+                message: line.clone(),
+            }),
+            None => Err(invalid_reply()),
+        }
+    }
+
+    /// Read a reply, up to the end line (`XYZ <text>`)
+    async fn read_reply(&mut self) -> Result<Vec<ReplyLine>, Error> {
+        let mut reply: Vec<ReplyLine> = Vec::new();
+
+        loop {
+            let line: String = self.read_line().await?;
+            if line.len() < 4 || !line.is_char_boundary(3) || !line.is_char_boundary(4) {
+                return Err(invalid_reply());
+            }
+
+            let code: u16 = line[..3].parse().map_err(|_| invalid_reply())?;
+            let text: String = line[4..].to_string();
+
+            match &line[3..4] {
+                " " => {
+                    reply.push((code, text, None));
+                    return Ok(reply);
+                }
+                "-" => reply.push((code, text, None)),
+                "+" => {
+                    let data: String = self.read_data().await?;
+                    reply.push((code, text, Some(data)));
+                }
+                _ => return Err(invalid_reply()),
+            }
+        }
+    }
+
+    /// Read a data block, up to the `.` line
+    async fn read_data(&mut self) -> Result<String, Error> {
+        let mut lines: Vec<String> = Vec::new();
+
+        loop {
+            let line: String = self.read_line().await?;
+            match line.as_str() {
+                "." => return Ok(lines.join("\n")),
+                // Remove the dot-stuffing
+                line => lines.push(line.strip_prefix('.').unwrap_or(line).to_string()),
+            }
+        }
+    }
+
+    async fn read_line(&mut self) -> Result<String, Error> {
+        let mut line: String = String::new();
+        let mut stream = (&mut self.stream).take(MAX_LINE_LENGTH);
+        if stream.read_line(&mut line).await? == 0 {
+            return Err(Error::Io(std::io::ErrorKind::UnexpectedEof.into()));
+        }
+
+        // The limit was reached before the end of the line
+        if !line.ends_with('\n') && stream.limit() == 0 {
+            return Err(Error::TorControl {
+                code: None,
+                message: format!("reply line longer than {MAX_LINE_LENGTH} bytes"),
+            });
+        }
+
+        Ok(line.trim_end_matches(['\r', '\n']).to_string())
+    }
+}
+
+#[inline]
+fn invalid_reply() -> Error {
+    Error::TorControl {
+        code: None,
+        message: String::from("invalid reply"),
+    }
+}
+
+/// The commands are line-based: a CR or LF would end the command and start a new one
+fn check_line(s: &str) -> Result<(), Error> {
+    if s.contains(['\r', '\n']) {
+        return Err(Error::TorControl {
+            code: None,
+            message: String::from("CR and LF aren't allowed in the command arguments"),
+        });
+    }
+    Ok(())
+}
+
+/// Quote the string, escaping the backslashes and the double quotes
+fn quote(s: &str) -> Result<String, Error> {
+    check_line(s)?;
+    let escaped: String = s.replace('\\', "\\\\").replace('"', "\\\"");
+    Ok(format!("\"{escaped}\""))
+}
+
+/// Circuits are isolated per host
+fn circuit_key(url: &Url) -> Result<String, Error> {
+    match url.host_str() {
+        Some(host) if !host.is_empty() => Ok(host.to_lowercase()),
+        _ => Err(Error::empty_host()),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .fold(String::with_capacity(bytes.len() * 2), |mut s, b| {
+            let _ = write!(s, "{b:02x}");
+            s
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Spawn a fake control port accepting the given password, answering a single connection
+    async fn spawn_control_port(password: &'static str) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+
+            let mut authenticated = false;
+            let mut line = String::new();
+            while stream.read_line(&mut line).await.unwrap() > 0 {
+                let reply: &str = match line.trim_end() {
+                    cmd if cmd == format!("AUTHENTICATE {}", quote(password).unwrap()) => {
+                        authenticated = true;
+                        "250 OK\r\n"
+                    }
+                    cmd if cmd.starts_with("AUTHENTICATE") => {
+                        "515 Authentication failed: Password did not match\r\n"
+                    }
+                    _ if !authenticated => "514 Authentication required.\r\n",
+                    "SIGNAL NEWNYM" => "250 OK\r\n",
+                    "GETINFO version" => "250-version=0.4.8.10\r\n250 OK\r\n",
+                    "GETINFO config-text" => {
+                        "250+config-text=\r\nSocksPort 9050\r\n..dot\r\n.\r\n250 OK\r\n"
+                    }
+                    _ => "552 Unrecognized key\r\n",
+                };
+                stream.get_mut().write_all(reply.as_bytes()).await.unwrap();
+                line.clear();
+            }
+        });
+
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_control() {
+        let addr: SocketAddr = spawn_control_port("pa\"ss").await;
+        let auth = TorAuth::Password(String::from("pa\"ss"));
+        let mut control = TorControl::connect(addr, &auth, DEFAULT_CONTROL_TIMEOUT)
+            .await
+            .unwrap();
+
+        assert_eq!(control.get_info("version").await.unwrap(), "0.4.8.10");
+        assert_eq!(
+            control.get_info("config-text").await.unwrap(),
+            "SocksPort 9050\n.dot"
+        );
+        control.signal_newnym().await.unwrap();
+        assert!(matches!(
+            control.get_info("version\r\nSIGNAL HALT").await,
+            Err(Error::TorControl { code: None, .. })
+        ));
+        assert!(matches!(
+            control.get_info("unknown").await,
+            Err(Error::TorControl {
+                code: Some(552),
+                ..
+            })
+        ));
+
+        // Wrong password
+        let addr: SocketAddr = spawn_control_port("pass").await;
+        let auth = TorAuth::Password(String::from("wrong"));
+        assert!(matches!(
+            TorControl::connect(addr, &auth, DEFAULT_CONTROL_TIMEOUT).await,
+            Err(Error::TorControl {
+                code: Some(515),
+                ..
+            })
+        ));
+
+        // Injected command
+        let auth = TorAuth::Password(String::from("pass\r\nSIGNAL HALT"));
+        assert!(matches!(
+            TorControl::connect(addr, &auth, DEFAULT_CONTROL_TIMEOUT).await,
+            Err(Error::TorControl { code: None, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_control_timeout() {
+        // Accept the connection, but never reply
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (_stream, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_secs(60)).await;
+        });
+
+        assert!(matches!(
+            TorControl::connect(addr, &TorAuth::None, Duration::from_millis(100)).await,
+            Err(Error::TorControl { code: None, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_control_line_too_long() {
+        // Reply with a line that never ends
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let chunk = vec![b'a'; 8 * 1024];
+            while stream.write_all(&chunk).await.is_ok() {}
+        });
+
+        match TorControl::connect(addr, &TorAuth::None, DEFAULT_CONTROL_TIMEOUT).await {
+            Err(Error::TorControl {
+                code: None,
+                message,
+            }) => assert!(message.contains("longer")),
+            res => panic!("unexpected result: {:?}", res.err()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_circuit_per_host() {
+        let addr: SocketAddr = spawn_control_port("pass").await;
+        let tor = Tor::new(SocketAddr::from(([127, 0, 0, 1], 9050)))
+            .control_port(addr, TorAuth::Password(String::from("pass")));
+
+        let relay = Url::parse("wss://relay.example.com").unwrap();
+        let other = Url::parse("wss://other.example.com").unwrap();
+
+        let first: Proxy = tor.proxy(&relay).unwrap();
+        assert_eq!(first, tor.proxy(&relay).unwrap());
+        assert_eq!(first, tor.clone().proxy(&relay).unwrap());
+        assert_ne!(first, tor.proxy(&other).unwrap());
+
+        tor.renew_circuit(&relay).unwrap();
+        let renewed: Proxy = tor.proxy(&relay).unwrap();
+        assert_ne!(first, renewed);
+
+        tor.new_identity().await.unwrap();
+        assert_ne!(renewed, tor.proxy(&relay).unwrap());
+
+        // No host: no shared circuit
+        let no_host = Url::parse("unix:/tmp/relay.sock").unwrap();
+        assert!(matches!(tor.proxy(&no_host), Err(Error::Url(..))));
+        assert!(matches!(tor.renew_circuit(&no_host), Err(Error::Url(..))));
+    }
+}
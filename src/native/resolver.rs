@@ -0,0 +1,221 @@
+// Copyright (c) 2022-2024 Yuki Kishimoto
+// Distributed under the MIT software license
+
+//! DNS resolver
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{fmt, io};
+
+/// Boxed future returned by [`Resolver::resolve`]
+pub type BoxedFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// DNS resolver, used to resolve the URL host before connecting
+pub trait Resolver: fmt::Debug + Send + Sync {
+    /// Resolve the host to a list of IP addresses
+    fn resolve<'a>(&'a self, host: &'a str) -> BoxedFuture<'a, io::Result<Vec<IpAddr>>>;
+}
+
+/// System resolver (default)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve<'a>(&'a self, host: &'a str) -> BoxedFuture<'a, io::Result<Vec<IpAddr>>> {
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((host, 0)).await?;
+            Ok(addrs.map(|addr| addr.ip()).collect())
+        })
+    }
+}
+
+/// Resolver with static host overrides (like curl's `--resolve`)
+///
+/// The hosts without an override are resolved by the fallback resolver (default: [`SystemResolver`]).
+#[derive(Debug, Clone)]
+pub struct StaticResolver {
+    hosts: HashMap<String, Vec<IpAddr>>,
+    fallback: Arc<dyn Resolver>,
+}
+
+impl Default for StaticResolver {
+    fn default() -> Self {
+        Self {
+            hosts: HashMap::new(),
+            fallback: Arc::new(SystemResolver),
+        }
+    }
+}
+
+impl StaticResolver {
+    /// New resolver without overrides
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Resolve the host to the given addresses
+    ///
+    /// The host is matched case-insensitively.
+    pub fn host<S, I>(mut self, host: S, addrs: I) -> Self
+    where
+        S: AsRef<str>,
+        I: IntoIterator<Item = IpAddr>,
+    {
+        self.hosts
+            .insert(host.as_ref().to_lowercase(), addrs.into_iter().collect());
+        self
+    }
+
+    /// Set the resolver for the hosts without an override
+    #[inline]
+    pub fn fallback(mut self, resolver: Arc<dyn Resolver>) -> Self {
+        self.fallback = resolver;
+        self
+    }
+}
+
+impl Resolver for StaticResolver {
+    fn resolve<'a>(&'a self, host: &'a str) -> BoxedFuture<'a, io::Result<Vec<IpAddr>>> {
+        match self.hosts.get(&host.to_lowercase()) {
+            Some(addrs) => {
+                let addrs: Vec<IpAddr> = addrs.clone();
+                Box::pin(async move { Ok(addrs) })
+            }
+            None => self.fallback.resolve(host),
+        }
+    }
+}
+
+/// Resolver that caches the results of another resolver for a fixed TTL
+///
+/// Failed resolutions aren't cached.
+#[derive(Debug)]
+pub struct CachingResolver {
+    inner: Arc<dyn Resolver>,
+    ttl: Duration,
+    cache: Mutex<HashMap<String, CacheEntry>>,
+}
+
+/// Expiration (`None` if the entry never expires) and cached addresses
+type CacheEntry = (Option<Instant>, Vec<IpAddr>);
+
+impl CachingResolver {
+    /// Cache the results of `inner` for `ttl`
+    ///
+    /// A `ttl` too large to be represented (i.e., `Duration::MAX`) caches the results forever.
+    pub fn new(inner: Arc<dyn Resolver>, ttl: Duration) -> Self {
+        Self {
+            inner,
+            ttl,
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Remove all the cached entries
+    pub fn clear(&self) {
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        cache.clear();
+    }
+
+    fn cached(&self, host: &str) -> Option<Vec<IpAddr>> {
+        let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+        match cache.get(host) {
+            Some((None, addrs)) => Some(addrs.clone()),
+            Some((Some(expire_at), addrs)) if Instant::now() < *expire_at => Some(addrs.clone()),
+            Some(..) => {
+                cache.remove(host);
+                None
+            }
+            None => None,
+        }
+    }
+}
+
+impl Resolver for CachingResolver {
+    fn resolve<'a>(&'a self, host: &'a str) -> BoxedFuture<'a, io::Result<Vec<IpAddr>>> {
+        Box::pin(async move {
+            let host: String = host.to_lowercase();
+
+            if let Some(addrs) = self.cached(&host) {
+                return Ok(addrs);
+            }
+
+            let addrs: Vec<IpAddr> = self.inner.resolve(&host).await?;
+
+            let mut cache = self.cache.lock().unwrap_or_else(|e| e.into_inner());
+            let expire_at: Option<Instant> = Instant::now().checked_add(self.ttl);
+            cache.insert(host, (expire_at, addrs.clone()));
+
+            Ok(addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct CountingResolver {
+        calls: AtomicUsize,
+    }
+
+    impl Resolver for CountingResolver {
+        fn resolve<'a>(&'a self, _host: &'a str) -> BoxedFuture<'a, io::Result<Vec<IpAddr>>> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Box::pin(async { Ok(vec![IpAddr::V4(Ipv4Addr::LOCALHOST)]) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_static_resolver() {
+        let ip = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let fallback = Arc::new(CountingResolver::default());
+        let resolver = StaticResolver::new()
+            .host("Relay.Example.com", [ip])
+            .fallback(fallback.clone());
+
+        assert_eq!(resolver.resolve("relay.example.com").await.unwrap(), [ip]);
+        assert_eq!(fallback.calls.load(Ordering::SeqCst), 0);
+
+        assert_eq!(
+            resolver.resolve("other.example.com").await.unwrap(),
+            [IpAddr::V4(Ipv4Addr::LOCALHOST)]
+        );
+        assert_eq!(fallback.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_caching_resolver() {
+        let inner = Arc::new(CountingResolver::default());
+        let resolver = CachingResolver::new(inner.clone(), Duration::from_secs(60));
+
+        resolver.resolve("relay.example.com").await.unwrap();
+        resolver.resolve("RELAY.example.com").await.unwrap();
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 1);
+
+        resolver.clear();
+        resolver.resolve("relay.example.com").await.unwrap();
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 2);
+
+        // Expired entries are resolved again
+        let resolver = CachingResolver::new(inner.clone(), Duration::ZERO);
+        resolver.resolve("relay.example.com").await.unwrap();
+        resolver.resolve("relay.example.com").await.unwrap();
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 4);
+
+        // Cached forever
+        let resolver = CachingResolver::new(inner.clone(), Duration::MAX);
+        resolver.resolve("relay.example.com").await.unwrap();
+        resolver.resolve("relay.example.com").await.unwrap();
+        assert_eq!(inner.calls.load(Ordering::SeqCst), 5);
+    }
+}
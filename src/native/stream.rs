@@ -0,0 +1,16 @@
+// Copyright (c) 2022-2024 Yuki Kishimoto
+// Distributed under the MIT software license
+
+//! Stream
+
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Byte stream the WebSocket connection runs over
+///
+/// Implemented for any `AsyncRead + AsyncWrite + Send + Unpin` type.
+pub trait AsyncStream: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T> AsyncStream for T where T: AsyncRead + AsyncWrite + Send + Unpin {}
+
+/// Type-erased stream (i.e., a TCP stream, a tunnel through a proxy or a user-provided stream)
+pub type BoxedStream = Box<dyn AsyncStream>;
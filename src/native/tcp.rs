@@ -0,0 +1,537 @@
+// Copyright (c) 2022-2024 Yuki Kishimoto
+// Distributed under the MIT software license
+
+//! TCP
+
+use std::io::{self, ErrorKind};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use futures_util::future::{self, Either};
+use futures_util::stream::{FuturesUnordered, StreamExt};
+use socket2::{SockRef, TcpKeepalive};
+use tokio::net::{TcpSocket, TcpStream};
+
+use crate::ConnectOptions;
+
+/// TCP socket options
+///
+/// Unset options keep the OS defaults.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TcpOptions {
+    nodelay: Option<bool>,
+    keepalive_idle: Option<Duration>,
+    keepalive_interval: Option<Duration>,
+    keepalive_retries: Option<u32>,
+    send_buffer_size: Option<usize>,
+    recv_buffer_size: Option<usize>,
+    linger: Option<Duration>,
+}
+
+impl TcpOptions {
+    /// New default options
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set `TCP_NODELAY`
+    #[inline]
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = Some(nodelay);
+        self
+    }
+
+    /// Enable the TCP keepalive, sending the first probe after the connection has been idle for `idle`
+    #[inline]
+    pub fn keepalive(mut self, idle: Duration) -> Self {
+        self.keepalive_idle = Some(idle);
+        self
+    }
+
+    /// Set the interval between the TCP keepalive probes
+    ///
+    /// Only used if the keepalive is enabled. Ignored on the platforms that don't support it.
+    #[inline]
+    pub fn keepalive_interval(mut self, interval: Duration) -> Self {
+        self.keepalive_interval = Some(interval);
+        self
+    }
+
+    /// Set the number of unanswered TCP keepalive probes before dropping the connection
+    ///
+    /// Only used if the keepalive is enabled. Ignored on the platforms that don't support it (i.e., Windows).
+    #[inline]
+    pub fn keepalive_retries(mut self, retries: u32) -> Self {
+        self.keepalive_retries = Some(retries);
+        self
+    }
+
+    /// Set the size of the send buffer (`SO_SNDBUF`)
+    #[inline]
+    pub fn send_buffer_size(mut self, size: usize) -> Self {
+        self.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Set the size of the receive buffer (`SO_RCVBUF`)
+    #[inline]
+    pub fn recv_buffer_size(mut self, size: usize) -> Self {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Set `SO_LINGER`
+    #[inline]
+    pub fn linger(mut self, linger: Duration) -> Self {
+        self.linger = Some(linger);
+        self
+    }
+
+    /// Apply the options to an accepted TCP stream
+    ///
+    /// Use it before [`accept`](crate::native::accept) for server-side connections.
+    #[inline]
+    pub fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        self.apply_to(SockRef::from(stream))
+    }
+
+    fn apply_to(&self, socket: SockRef<'_>) -> io::Result<()> {
+        if let Some(nodelay) = self.nodelay {
+            socket.set_nodelay(nodelay)?;
+        }
+
+        if let Some(idle) = self.keepalive_idle {
+            socket.set_tcp_keepalive(&self.tcp_keepalive(idle))?;
+        }
+
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+
+        if let Some(linger) = self.linger {
+            socket.set_linger(Some(linger))?;
+        }
+
+        Ok(())
+    }
+
+    #[allow(unused_mut)]
+    fn tcp_keepalive(&self, idle: Duration) -> TcpKeepalive {
+        let mut keepalive: TcpKeepalive = TcpKeepalive::new().with_time(idle);
+
+        #[cfg(any(
+            target_os = "android",
+            target_os = "dragonfly",
+            target_os = "freebsd",
+            target_os = "fuchsia",
+            target_os = "illumos",
+            target_os = "ios",
+            target_os = "linux",
+            target_os = "macos",
+            target_os = "netbsd",
+            target_os = "tvos",
+            target_os = "watchos",
+            target_os = "windows",
+        ))]
+        if let Some(interval) = self.keepalive_interval {
+            keepalive = keepalive.with_interval(interval);
+        }
+
+        #[cfg(any(
+            target_os = "android",
+            target_os = "dragonfly",
+            target_os = "freebsd",
+            target_os = "fuchsia",
+            target_os = "illumos",
+            target_os = "ios",
+            target_os = "linux",
+            target_os = "macos",
+            target_os = "netbsd",
+            target_os = "tvos",
+            target_os = "watchos",
+        ))]
+        if let Some(retries) = self.keepalive_retries {
+            keepalive = keepalive.with_retries(retries);
+        }
+
+        keepalive
+    }
+}
+
+/// IP family preference
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum IpPreference {
+    /// Only use IPv4 addresses
+    Ipv4Only,
+    /// Only use IPv6 addresses
+    Ipv6Only,
+    /// Try IPv4 first, then fall back to IPv6
+    PreferIpv4,
+    /// Try IPv6 first, then fall back to IPv4
+    #[default]
+    PreferIpv6,
+}
+
+impl IpPreference {
+    /// Check if the address can be used
+    pub(super) fn allows(&self, addr: &SocketAddr) -> bool {
+        match self {
+            Self::Ipv4Only => addr.is_ipv4(),
+            Self::Ipv6Only => addr.is_ipv6(),
+            Self::PreferIpv4 | Self::PreferIpv6 => true,
+        }
+    }
+}
+
+/// Happy Eyeballs config (RFC 8305)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HappyEyeballsConfig {
+    preference: IpPreference,
+    attempt_delay: Duration,
+    max_parallel_attempts: Option<usize>,
+}
+
+impl Default for HappyEyeballsConfig {
+    fn default() -> Self {
+        Self {
+            preference: IpPreference::default(),
+            attempt_delay: Duration::from_millis(250),
+            max_parallel_attempts: None,
+        }
+    }
+}
+
+impl HappyEyeballsConfig {
+    /// New default config
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the IP family preference (default: [`IpPreference::PreferIpv6`])
+    ///
+    /// Also applies to the connection to the proxy.
+    #[inline]
+    pub fn preference(mut self, preference: IpPreference) -> Self {
+        self.preference = preference;
+        self
+    }
+
+    /// Set the delay before starting the next connection attempt, while the previous ones are pending (default: 250 ms)
+    #[inline]
+    pub fn attempt_delay(mut self, delay: Duration) -> Self {
+        self.attempt_delay = delay;
+        self
+    }
+
+    /// Set the max number of parallel connection attempts (default: unlimited)
+    ///
+    /// `1` disables Happy Eyeballs: the addresses are tried one by one.
+    /// `0` is treated as `1`.
+    #[inline]
+    pub fn max_parallel_attempts(mut self, max: usize) -> Self {
+        self.max_parallel_attempts = Some(max.max(1));
+        self
+    }
+}
+
+/// Connect to the first reachable address, using the Happy Eyeballs algorithm (RFC 8305)
+///
+/// The addresses are interleaved by family, starting with the preferred one. A new attempt is started
+/// after the attempt delay (or as soon as the previous one fails) while the previous ones are still pending.
+/// With a local address, the addresses of the other family are skipped.
+pub(super) async fn connect(addrs: &[SocketAddr], opts: &ConnectOptions) -> io::Result<TcpStream> {
+    let config: &HappyEyeballsConfig = &opts.happy_eyeballs;
+    let max_parallel_attempts: usize = config.max_parallel_attempts.unwrap_or(usize::MAX);
+
+    let addrs: Vec<SocketAddr> = match opts.local_address {
+        Some(ip) => {
+            let addrs: Vec<SocketAddr> = addrs
+                .iter()
+                .filter(|addr| addr.is_ipv4() == ip.is_ipv4())
+                .copied()
+                .collect();
+            if addrs.is_empty() {
+                return Err(io::Error::new(
+                    ErrorKind::AddrNotAvailable,
+                    format!("no address to connect to from the local address {ip}"),
+                ));
+            }
+            addrs
+        }
+        None => addrs.to_vec(),
+    };
+
+    let mut addrs = interleave(&addrs, config.preference).into_iter();
+    let mut pending = FuturesUnordered::new();
+    let mut last_err: Option<io::Error> = None;
+
+    loop {
+        if pending.is_empty() {
+            match addrs.next() {
+                Some(addr) => pending.push(connect_addr(addr, opts)),
+                None => {
+                    return Err(last_err.unwrap_or_else(|| {
+                        io::Error::new(ErrorKind::AddrNotAvailable, "no addresses to connect to")
+                    }))
+                }
+            }
+        }
+
+        let res: Option<io::Result<TcpStream>> =
+            if addrs.len() > 0 && pending.len() < max_parallel_attempts {
+                let delay = tokio::time::sleep(config.attempt_delay);
+                tokio::pin!(delay);
+
+                match future::select(pending.next(), delay).await {
+                    Either::Left((res, _)) => res,
+                    Either::Right(..) => {
+                        // Start the next attempt, keeping the pending ones
+                        if let Some(addr) = addrs.next() {
+                            pending.push(connect_addr(addr, opts));
+                        }
+                        continue;
+                    }
+                }
+            } else {
+                pending.next().await
+            };
+
+        match res {
+            Some(Ok(stream)) => return Ok(stream),
+            Some(Err(e)) => {
+                last_err = Some(e);
+
+                // Start the next attempt immediately
+                if let Some(addr) = addrs.next() {
+                    pending.push(connect_addr(addr, opts));
+                }
+            }
+            None => {}
+        }
+    }
+}
+
+/// Connect to a single address, applying the socket and local bind options
+async fn connect_addr(addr: SocketAddr, opts: &ConnectOptions) -> io::Result<TcpStream> {
+    let socket: TcpSocket = match addr {
+        SocketAddr::V4(..) => TcpSocket::new_v4()?,
+        SocketAddr::V6(..) => TcpSocket::new_v6()?,
+    };
+
+    #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+    if let Some(interface) = &opts.interface {
+        socket.bind_device(Some(interface.as_bytes()))?;
+    }
+
+    // Set the options before connecting, so the buffer sizes are used for the TCP window negotiation
+    opts.tcp.apply_to(SockRef::from(&socket))?;
+
+    bind(&socket, addr, opts)?;
+
+    socket.connect(addr).await
+}
+
+/// Bind the socket to the local address and port range, if set
+fn bind(socket: &TcpSocket, addr: SocketAddr, opts: &ConnectOptions) -> io::Result<()> {
+    let ip: IpAddr = match (opts.local_address, addr) {
+        (Some(ip), _) if ip.is_ipv4() == addr.is_ipv4() => ip,
+        (Some(ip), _) => {
+            return Err(io::Error::new(
+                ErrorKind::AddrNotAvailable,
+                format!("local address {ip} can't be used to connect to {addr}"),
+            ))
+        }
+        (None, _) if opts.local_port_range.is_none() => return Ok(()),
+        (None, SocketAddr::V4(..)) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        (None, SocketAddr::V6(..)) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+
+    let ports = match &opts.local_port_range {
+        Some(range) => range.clone(),
+        None => return socket.bind(SocketAddr::new(ip, 0)),
+    };
+
+    // Use the first free port of the range
+    for port in ports {
+        match socket.bind(SocketAddr::new(ip, port)) {
+            Ok(()) => return Ok(()),
+            Err(e) if e.kind() == ErrorKind::AddrInUse => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(io::Error::new(
+        ErrorKind::AddrInUse,
+        "no free port in the local port range",
+    ))
+}
+
+/// Sort the addresses according to the IP family preference, removing the ones not allowed
+pub(super) fn sort_addrs(addrs: &[SocketAddr], opts: &ConnectOptions) -> Vec<SocketAddr> {
+    interleave(addrs, opts.happy_eyeballs.preference)
+}
+
+/// Interleave the addresses by family, starting with the preferred one and preserving the order within each family
+///
+/// The addresses not allowed by the preference are removed.
+fn interleave(addrs: &[SocketAddr], preference: IpPreference) -> Vec<SocketAddr> {
+    let (ipv6, ipv4): (Vec<SocketAddr>, Vec<SocketAddr>) = addrs
+        .iter()
+        .filter(|addr| preference.allows(addr))
+        .partition(|addr| addr.is_ipv6());
+
+    let (first, second) = match preference {
+        IpPreference::Ipv4Only | IpPreference::PreferIpv4 => (ipv4, ipv6),
+        IpPreference::Ipv6Only | IpPreference::PreferIpv6 => (ipv6, ipv4),
+    };
+
+    let mut first = first.into_iter();
+    let mut second = second.into_iter();
+    let mut addrs: Vec<SocketAddr> = Vec::with_capacity(addrs.len());
+
+    loop {
+        match (first.next(), second.next()) {
+            (None, None) => break,
+            (a, b) => addrs.extend(a.into_iter().chain(b)),
+        }
+    }
+
+    addrs
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Spawn a TCP server that echoes back everything it receives
+    pub(crate) async fn spawn_echo_server() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (mut reader, mut writer) = stream.split();
+                    let _ = tokio::io::copy(&mut reader, &mut writer).await;
+                });
+            }
+        });
+
+        addr
+    }
+
+    #[test]
+    fn test_interleave() {
+        let addrs: Vec<SocketAddr> = ["1.1.1.1:80", "2.2.2.2:80", "[::1]:80", "3.3.3.3:80"]
+            .iter()
+            .map(|addr| addr.parse().unwrap())
+            .collect();
+
+        let expected: Vec<SocketAddr> = ["[::1]:80", "1.1.1.1:80", "2.2.2.2:80", "3.3.3.3:80"]
+            .iter()
+            .map(|addr| addr.parse().unwrap())
+            .collect();
+
+        assert_eq!(interleave(&addrs, IpPreference::PreferIpv6), expected);
+
+        let expected: Vec<SocketAddr> = ["1.1.1.1:80", "[::1]:80", "2.2.2.2:80", "3.3.3.3:80"]
+            .iter()
+            .map(|addr| addr.parse().unwrap())
+            .collect();
+        assert_eq!(interleave(&addrs, IpPreference::PreferIpv4), expected);
+
+        assert_eq!(interleave(&addrs, IpPreference::Ipv4Only).len(), 3);
+        assert_eq!(
+            interleave(&addrs, IpPreference::Ipv6Only),
+            ["[::1]:80".parse().unwrap()]
+        );
+    }
+
+    #[test]
+    fn test_max_parallel_attempts() {
+        assert_eq!(
+            HappyEyeballsConfig::new().max_parallel_attempts(0),
+            HappyEyeballsConfig::new().max_parallel_attempts(1)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_happy_eyeballs_fallback() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+
+        // Closed port, tried first
+        let closed: SocketAddr = {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            listener.local_addr().unwrap()
+        };
+
+        let config = HappyEyeballsConfig::new().max_parallel_attempts(1);
+        let opts = ConnectOptions::new().happy_eyeballs(config);
+        let stream = connect(&[closed, addr], &opts).await.unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), addr);
+
+        // IPv6 only: no usable address
+        let config = HappyEyeballsConfig::new().preference(IpPreference::Ipv6Only);
+        let opts = ConnectOptions::new().happy_eyeballs(config);
+        let e = connect(&[addr], &opts).await.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::AddrNotAvailable);
+    }
+
+    #[tokio::test]
+    async fn test_local_bind() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+
+        let opts = ConnectOptions::new().local_address(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let stream = connect(&[addr], &opts).await.unwrap();
+        assert_eq!(stream.local_addr().unwrap().ip(), Ipv4Addr::LOCALHOST);
+
+        // The addresses of the other family are skipped
+        let ipv6: SocketAddr = SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), addr.port());
+        let opts = ConnectOptions::new()
+            .local_address(IpAddr::V4(Ipv4Addr::LOCALHOST))
+            .happy_eyeballs(HappyEyeballsConfig::new().preference(IpPreference::PreferIpv6));
+        let stream = connect(&[ipv6, addr], &opts).await.unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), addr);
+
+        // Family mismatch
+        let opts = ConnectOptions::new().local_address(IpAddr::V6(Ipv6Addr::LOCALHOST));
+        let e = connect(&[addr], &opts).await.unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::AddrNotAvailable);
+    }
+
+    #[tokio::test]
+    async fn test_tcp_options() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr: SocketAddr = listener.local_addr().unwrap();
+
+        let tcp = TcpOptions::new()
+            .nodelay(true)
+            .keepalive(Duration::from_secs(30))
+            .keepalive_interval(Duration::from_secs(5))
+            .keepalive_retries(3)
+            .linger(Duration::from_secs(1));
+        let stream = connect(&[addr], &ConnectOptions::new().tcp(tcp.clone()))
+            .await
+            .unwrap();
+
+        let socket = SockRef::from(&stream);
+        assert!(socket.nodelay().unwrap());
+        assert!(socket.keepalive().unwrap());
+        assert_eq!(socket.linger().unwrap(), Some(Duration::from_secs(1)));
+
+        // Server side
+        let (stream, _) = listener.accept().await.unwrap();
+        tcp.apply(&stream).unwrap();
+        assert!(SockRef::from(&stream).nodelay().unwrap());
+    }
+}
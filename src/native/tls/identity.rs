@@ -0,0 +1,105 @@
+// Copyright (c) 2022-2024 Yuki Kishimoto
+// Distributed under the MIT software license
+
+//! Client identity
+
+use std::fmt;
+
+/// Client certificate and private key, used for mutual TLS authentication
+#[derive(Clone)]
+pub struct ClientIdentity {
+    pub(super) inner: InnerIdentity,
+}
+
+#[derive(Clone)]
+#[cfg_attr(
+    not(any(feature = "native-tls", feature = "__rustls")),
+    allow(dead_code)
+)]
+pub(super) enum InnerIdentity {
+    Pem {
+        cert_chain: Vec<u8>,
+        key: Vec<u8>,
+    },
+    Der {
+        cert_chain: Vec<Vec<u8>>,
+        key: Vec<u8>,
+    },
+    #[cfg(feature = "native-tls")]
+    Pkcs12 {
+        der: Vec<u8>,
+        password: String,
+    },
+}
+
+impl fmt::Debug for ClientIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Never print the private key
+        let format: &str = match self.inner {
+            InnerIdentity::Pem { .. } => "PEM",
+            InnerIdentity::Der { .. } => "DER",
+            #[cfg(feature = "native-tls")]
+            InnerIdentity::Pkcs12 { .. } => "PKCS#12",
+        };
+        f.debug_struct("ClientIdentity")
+            .field("format", &format)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ClientIdentity {
+    /// Identity from a PEM-encoded certificate chain (leaf first) and a PEM-encoded private key
+    ///
+    /// `rustls` accepts PKCS#8 (`BEGIN PRIVATE KEY`), PKCS#1 (`BEGIN RSA PRIVATE KEY`) and SEC1 (`BEGIN EC PRIVATE KEY`) keys,
+    /// `native-tls` only PKCS#8 keys. For DER-encoded data, use [`ClientIdentity::from_der`].
+    ///
+    /// The PEM data is parsed when connecting: the connection fails with [`Error::InvalidClientIdentity`] if it can't be used.
+    ///
+    /// [`Error::InvalidClientIdentity`]: crate::native::Error::InvalidClientIdentity
+    pub fn from_pem<C, K>(cert_chain: C, key: K) -> Self
+    where
+        C: Into<Vec<u8>>,
+        K: Into<Vec<u8>>,
+    {
+        Self {
+            inner: InnerIdentity::Pem {
+                cert_chain: cert_chain.into(),
+                key: key.into(),
+            },
+        }
+    }
+
+    /// Identity from DER-encoded certificates (leaf first) and a DER-encoded private key
+    ///
+    /// Supported key formats are the same of [`ClientIdentity::from_pem`]: PKCS#8, PKCS#1 and SEC1 with `rustls`,
+    /// only PKCS#8 with `native-tls`.
+    ///
+    /// The data is parsed when connecting: the connection fails with [`Error::InvalidClientIdentity`] if it can't be used.
+    ///
+    /// [`Error::InvalidClientIdentity`]: crate::native::Error::InvalidClientIdentity
+    pub fn from_der(cert_chain: Vec<Vec<u8>>, key: Vec<u8>) -> Self {
+        Self {
+            inner: InnerIdentity::Der { cert_chain, key },
+        }
+    }
+
+    /// Identity from a DER-encoded PKCS#12 archive
+    ///
+    /// Only supported by the `native-tls` backend: with a custom `rustls` config,
+    /// the connection fails with [`Error::InvalidClientIdentity`].
+    ///
+    /// [`Error::InvalidClientIdentity`]: crate::native::Error::InvalidClientIdentity
+    #[cfg(feature = "native-tls")]
+    pub fn from_pkcs12<D, P>(der: D, password: P) -> Self
+    where
+        D: Into<Vec<u8>>,
+        P: Into<String>,
+    {
+        Self {
+            inner: InnerIdentity::Pkcs12 {
+                der: der.into(),
+                password: password.into(),
+            },
+        }
+    }
+}
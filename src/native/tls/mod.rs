@@ -0,0 +1,577 @@
+// Copyright (c) 2022-2024 Yuki Kishimoto
+// Distributed under the MIT software license
+
+//! TLS
+
+#[cfg(feature = "danger-insecure-tls")]
+use std::fmt;
+#[cfg(feature = "__rustls")]
+use std::sync::Arc;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+#[cfg(feature = "__rustls")]
+pub use tokio_rustls::rustls;
+#[cfg(feature = "__rustls")]
+use tokio_rustls::rustls::ClientConfig;
+use tokio_tungstenite::tungstenite::error::UrlError;
+use tokio_tungstenite::tungstenite::Error as WsError;
+use tokio_tungstenite::MaybeTlsStream;
+use url::{Host, Url};
+
+mod identity;
+#[cfg(feature = "native-tls")]
+mod native_tls;
+#[cfg(any(feature = "native-tls", feature = "__rustls"))]
+mod pin;
+#[cfg(feature = "__rustls")]
+mod rustls_tls;
+
+pub use self::identity::ClientIdentity;
+use super::Error;
+
+/// TLS config
+///
+/// By default, the TLS backend enabled by the crate features is used with its default roots:
+/// `native-tls` is preferred if both `native-tls` and `rustls` are enabled.
+///
+/// With `rustls` and no roots feature (`rustls-tls-native-roots` or `rustls-tls-webpki-roots`),
+/// set a custom config with [`TlsConfig::rustls`] (or rely on the pins only): otherwise the connection fails
+/// with [`UrlError::TlsFeatureNotEnabled`](tokio_tungstenite::tungstenite::error::UrlError::TlsFeatureNotEnabled).
+#[derive(Debug, Clone, Default)]
+pub struct TlsConfig {
+    #[cfg(feature = "__rustls")]
+    rustls: Option<Arc<ClientConfig>>,
+    identity: Option<ClientIdentity>,
+    pins: Vec<[u8; 32]>,
+    pins_only: bool,
+    #[cfg(feature = "danger-insecure-tls")]
+    danger: Danger,
+}
+
+/// Insecure settings, for development only
+#[cfg(feature = "danger-insecure-tls")]
+#[derive(Clone, Copy, Default)]
+struct Danger {
+    accept_invalid_certs: bool,
+    accept_invalid_hostnames: bool,
+}
+
+#[cfg(feature = "danger-insecure-tls")]
+impl fmt::Debug for Danger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.accept_invalid_certs, self.accept_invalid_hostnames) {
+            (false, false) => write!(f, "None"),
+            (true, false) => write!(f, "!!! INSECURE: accepting invalid certificates !!!"),
+            (false, true) => write!(f, "!!! INSECURE: accepting invalid hostnames !!!"),
+            (true, true) => write!(
+                f,
+                "!!! INSECURE: accepting invalid certificates and hostnames !!!"
+            ),
+        }
+    }
+}
+
+impl TlsConfig {
+    /// New default TLS config
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use a custom `rustls` client config
+    ///
+    /// Allows to use custom root CAs, ALPN protocols or a specific crypto provider.
+    /// Takes precedence over `native-tls`, if also enabled.
+    #[inline]
+    #[cfg(feature = "__rustls")]
+    pub fn rustls(mut self, config: Arc<ClientConfig>) -> Self {
+        self.rustls = Some(config);
+        self
+    }
+
+    /// Set the client certificate to use for mutual TLS authentication
+    #[inline]
+    pub fn client_identity(mut self, identity: ClientIdentity) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// Pin the SHA-256 hash of the SubjectPublicKeyInfo (SPKI) of a certificate
+    ///
+    /// The connection fails with [`Error::CertificatePinMismatch`] if no server certificate matches a pin.
+    /// The end-entity certificate can always be pinned. With `rustls` and chain validation, the presented
+    /// intermediates the end-entity certificate chains up to can be pinned too; the extra certificates are ignored.
+    #[inline]
+    pub fn pin_spki_sha256(mut self, pin: [u8; 32]) -> Self {
+        self.pins.push(pin);
+        self
+    }
+
+    /// Skip the certificate chain validation and rely only on the pins (default: false)
+    ///
+    /// Ignored if no pin is set.
+    #[inline]
+    pub fn pins_only(mut self, pins_only: bool) -> Self {
+        self.pins_only = pins_only;
+        self
+    }
+
+    /// Accept **any** server certificate, including expired and self-signed ones
+    ///
+    /// **DANGER**: this makes the connection vulnerable to man-in-the-middle attacks.
+    /// Only meant for development (i.e., `wss://localhost` with a self-signed certificate).
+    #[inline]
+    #[cfg(feature = "danger-insecure-tls")]
+    pub fn danger_accept_invalid_certs(mut self, accept: bool) -> Self {
+        self.danger.accept_invalid_certs = accept;
+        self
+    }
+
+    /// Accept server certificates that don't match the hostname
+    ///
+    /// **DANGER**: this makes the connection vulnerable to man-in-the-middle attacks.
+    /// Only meant for development.
+    ///
+    /// With a custom `rustls` config, this is ignored: set a custom certificate verifier in the config instead.
+    #[inline]
+    #[cfg(feature = "danger-insecure-tls")]
+    pub fn danger_accept_invalid_hostnames(mut self, accept: bool) -> Self {
+        self.danger.accept_invalid_hostnames = accept;
+        self
+    }
+
+    /// Check if the certificate chain validation must be skipped
+    #[cfg(any(feature = "native-tls", feature = "__rustls"))]
+    fn skip_chain_validation(&self) -> bool {
+        #[cfg(feature = "danger-insecure-tls")]
+        if self.danger.accept_invalid_certs {
+            return true;
+        }
+
+        self.pins_only && !self.pins.is_empty()
+    }
+
+    /// Check if the `rustls` backend is used
+    #[cfg(any(feature = "native-tls", feature = "__rustls"))]
+    fn uses_rustls(&self) -> bool {
+        #[cfg(feature = "native-tls")]
+        {
+            #[cfg(feature = "__rustls")]
+            if self.rustls.is_some() {
+                return true;
+            }
+
+            false
+        }
+
+        #[cfg(not(feature = "native-tls"))]
+        {
+            true
+        }
+    }
+
+    /// Check if the hostname validation must be skipped
+    #[cfg(any(feature = "native-tls", feature = "__rustls"))]
+    fn skip_hostname_validation(&self) -> bool {
+        #[cfg(feature = "danger-insecure-tls")]
+        if self.danger.accept_invalid_hostnames {
+            return true;
+        }
+
+        false
+    }
+}
+
+/// Upgrade the stream to TLS, if required by the URL scheme
+pub(super) async fn wrap_stream<S>(
+    url: &Url,
+    stream: S,
+    config: &TlsConfig,
+) -> Result<MaybeTlsStream<S>, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    match url.scheme() {
+        "ws" => Ok(MaybeTlsStream::Plain(stream)),
+        "wss" => {
+            let domain: String = domain(url)?;
+            connect(&domain, stream, config).await
+        }
+        _ => Err(WsError::Url(UrlError::UnsupportedUrlScheme).into()),
+    }
+}
+
+/// Get the domain to verify the certificate against
+fn domain(url: &Url) -> Result<String, Error> {
+    let host: Host<&str> = url.host().ok_or_else(Error::empty_host)?;
+    Ok(host_name(host))
+}
+
+/// Get the name to verify the certificate against
+///
+/// IPv6 addresses are returned without the surrounding brackets.
+pub(super) fn host_name(host: Host<&str>) -> String {
+    match host {
+        Host::Domain(domain) => domain.to_string(),
+        Host::Ipv4(ip) => ip.to_string(),
+        Host::Ipv6(ip) => ip.to_string(),
+    }
+}
+
+#[cfg(not(any(feature = "native-tls", feature = "__rustls")))]
+pub(super) async fn connect<S>(
+    _domain: &str,
+    _stream: S,
+    _config: &TlsConfig,
+) -> Result<MaybeTlsStream<S>, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    Err(WsError::Url(UrlError::TlsFeatureNotEnabled).into())
+}
+
+/// Connect with the TLS backend, verifying the pins (if any) before sending any data
+#[cfg(any(feature = "native-tls", feature = "__rustls"))]
+pub(super) async fn connect<S>(
+    domain: &str,
+    stream: S,
+    config: &TlsConfig,
+) -> Result<MaybeTlsStream<S>, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    #[cfg(feature = "__rustls")]
+    if let Some(rustls) = &config.rustls {
+        return rustls_tls::connect(domain, stream, rustls.clone(), config).await;
+    }
+
+    #[cfg(feature = "native-tls")]
+    {
+        native_tls::connect(domain, stream, config).await
+    }
+
+    #[cfg(all(feature = "__rustls", not(feature = "native-tls")))]
+    {
+        let rustls: Arc<ClientConfig> = rustls_tls::default_config(config)?;
+        rustls_tls::connect(domain, stream, rustls, config).await
+    }
+}
+
+/// Map the errors caused by the server rejecting the client certificate
+///
+/// With TLS 1.3 the server verifies the client certificate after the handshake,
+/// so the alert may be received during the TLS handshake or during the HTTP upgrade.
+/// Only the connections with a client identity are affected.
+#[allow(unused_variables)]
+pub(super) fn map_error(config: &TlsConfig, e: Error) -> Error {
+    if config.identity.is_none() {
+        return e;
+    }
+
+    let rejected: bool = match &e {
+        // `rustls` exposes the received alert
+        #[cfg(feature = "__rustls")]
+        Error::Io(e) | Error::Ws(WsError::Io(e)) if config.uses_rustls() => {
+            rustls_tls::is_client_certificate_rejected(e)
+        }
+        // The platform TLS libraries don't: fall back to the error message
+        #[cfg(feature = "native-tls")]
+        Error::Io(e) | Error::Ws(WsError::Io(e)) if !config.uses_rustls() => {
+            native_tls::is_client_certificate_rejected(e)
+        }
+        #[cfg(feature = "native-tls")]
+        Error::Ws(WsError::Tls(e)) if !config.uses_rustls() => {
+            native_tls::is_client_certificate_rejected(e)
+        }
+        _ => false,
+    };
+
+    if rejected {
+        Error::ClientCertificateRejected
+    } else {
+        e
+    }
+}
+
+#[cfg(all(test, feature = "ring"))]
+mod tests {
+    use rustls::crypto::ring;
+    use rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer};
+    use rustls::server::WebPkiClientVerifier;
+    use rustls::{RootCertStore, ServerConfig};
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    use super::*;
+    use crate::ConnectOptions;
+
+    /// Spawn a TLS WebSocket server with a self-signed certificate for `localhost`
+    ///
+    /// If `client_ca` is set, the server requires a client certificate signed by it.
+    async fn spawn_server(
+        client_ca: Option<CertificateDer<'static>>,
+    ) -> (Url, CertificateDer<'static>) {
+        let cert = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+        let cert_der: CertificateDer<'static> = cert.cert.der().clone();
+        let key = PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der());
+
+        let url = spawn_server_with_chain(vec![cert_der.clone()], key, client_ca).await;
+        (url, cert_der)
+    }
+
+    /// Spawn a TLS WebSocket server presenting the given certificates
+    async fn spawn_server_with_chain(
+        certs: Vec<CertificateDer<'static>>,
+        key: PrivatePkcs8KeyDer<'static>,
+        client_ca: Option<CertificateDer<'static>>,
+    ) -> Url {
+        let provider = Arc::new(ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .unwrap();
+        let builder = match client_ca {
+            Some(ca) => {
+                let mut roots = RootCertStore::empty();
+                roots.add(ca).unwrap();
+                let verifier =
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                        .build()
+                        .unwrap();
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let config = builder.with_single_cert(certs, key.into()).unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port: u16 = listener.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    if let Ok(stream) = acceptor.accept(stream).await {
+                        let _ = tokio_tungstenite::accept_async(stream).await;
+                    }
+                });
+            }
+        });
+
+        Url::parse(&format!("wss://localhost:{port}")).unwrap()
+    }
+
+    /// Generate a CA and a `localhost` certificate issued by it
+    fn generate_ca_chain() -> (
+        CertificateDer<'static>,
+        CertificateDer<'static>,
+        PrivatePkcs8KeyDer<'static>,
+    ) {
+        let ca_key = rcgen::KeyPair::generate().unwrap();
+        let mut params = rcgen::CertificateParams::new(Vec::new()).unwrap();
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca = params.self_signed(&ca_key).unwrap();
+
+        let key = rcgen::KeyPair::generate().unwrap();
+        let params = rcgen::CertificateParams::new(vec![String::from("localhost")]).unwrap();
+        let cert = params.signed_by(&key, &ca, &ca_key).unwrap();
+
+        (
+            ca.der().clone(),
+            cert.der().clone(),
+            PrivatePkcs8KeyDer::from(key.serialize_der()),
+        )
+    }
+
+    fn client_config(roots: RootCertStore) -> Arc<ClientConfig> {
+        let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        Arc::new(config)
+    }
+
+    #[tokio::test]
+    async fn test_custom_rustls_config() {
+        let (url, cert) = spawn_server(None).await;
+
+        let mut roots = RootCertStore::empty();
+        roots.add(cert).unwrap();
+        let opts = ConnectOptions::new().tls(TlsConfig::new().rustls(client_config(roots)));
+
+        assert!(crate::native::connect(&url, &opts).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_untrusted_certificate() {
+        let (url, _) = spawn_server(None).await;
+
+        let opts = ConnectOptions::new()
+            .tls(TlsConfig::new().rustls(client_config(RootCertStore::empty())));
+
+        assert!(crate::native::connect(&url, &opts).await.is_err());
+    }
+
+    #[tokio::test]
+    #[cfg(not(any(
+        feature = "native-tls",
+        feature = "rustls-tls-native-roots",
+        feature = "rustls-tls-webpki-roots"
+    )))]
+    async fn test_no_roots() {
+        let (url, cert) = spawn_server(None).await;
+
+        let opts = ConnectOptions::new();
+        assert!(matches!(
+            crate::native::connect(&url, &opts).await,
+            Err(Error::Ws(WsError::Url(UrlError::TlsFeatureNotEnabled)))
+        ));
+
+        // The pins don't need roots
+        let pin: [u8; 32] = pin::spki_sha256(&cert).unwrap();
+        let opts = ConnectOptions::new().tls(TlsConfig::new().pin_spki_sha256(pin).pins_only(true));
+        assert!(crate::native::connect(&url, &opts).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_client_identity() {
+        let client = rcgen::generate_simple_self_signed(vec![String::from("client")]).unwrap();
+        let (url, cert) = spawn_server(Some(client.cert.der().clone())).await;
+
+        let mut roots = RootCertStore::empty();
+        roots.add(cert).unwrap();
+        let config = TlsConfig::new().rustls(client_config(roots));
+
+        // Without client certificate: the errors aren't mapped
+        let opts = ConnectOptions::new().tls(config.clone());
+        assert!(matches!(
+            crate::native::connect(&url, &opts).await,
+            Err(Error::Io(..) | Error::Ws(..))
+        ));
+
+        // With an untrusted client certificate
+        let other_key = rcgen::KeyPair::generate().unwrap();
+        let mut params = rcgen::CertificateParams::new(vec![String::from("client")]).unwrap();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "untrusted");
+        let other = params.self_signed(&other_key).unwrap();
+        let identity = ClientIdentity::from_pem(other.pem(), other_key.serialize_pem());
+        let opts = ConnectOptions::new().tls(config.clone().client_identity(identity));
+        match crate::native::connect(&url, &opts).await {
+            Err(Error::ClientCertificateRejected) => {}
+            res => panic!("unexpected result: {:?}", res.err()),
+        }
+
+        // DER-encoded data isn't valid PEM
+        let identity =
+            ClientIdentity::from_pem(client.cert.der().to_vec(), client.key_pair.serialize_der());
+        let opts = ConnectOptions::new().tls(config.clone().client_identity(identity));
+        assert!(matches!(
+            crate::native::connect(&url, &opts).await,
+            Err(Error::InvalidClientIdentity(..))
+        ));
+
+        // With DER-encoded client certificate
+        let identity = ClientIdentity::from_der(
+            vec![client.cert.der().to_vec()],
+            client.key_pair.serialize_der(),
+        );
+        let opts = ConnectOptions::new().tls(config.clone().client_identity(identity));
+        assert!(crate::native::connect(&url, &opts).await.is_ok());
+
+        // With client certificate
+        let identity = ClientIdentity::from_pem(client.cert.pem(), client.key_pair.serialize_pem());
+        let opts = ConnectOptions::new().tls(config.client_identity(identity));
+        assert!(crate::native::connect(&url, &opts).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_certificate_pinning() {
+        let (url, cert) = spawn_server(None).await;
+        let pin: [u8; 32] = pin::spki_sha256(&cert).unwrap();
+
+        // Pin only, without trusted roots
+        let config = TlsConfig::new().rustls(client_config(RootCertStore::empty()));
+        let opts = ConnectOptions::new().tls(config.clone().pin_spki_sha256(pin).pins_only(true));
+        assert!(crate::native::connect(&url, &opts).await.is_ok());
+
+        // Wrong pin
+        let mut roots = RootCertStore::empty();
+        roots.add(cert).unwrap();
+        let config = TlsConfig::new().rustls(client_config(roots));
+        let opts = ConnectOptions::new().tls(config.clone().pin_spki_sha256([0; 32]));
+        match crate::native::connect(&url, &opts).await {
+            Err(Error::CertificatePinMismatch { presented }) => assert_eq!(presented, vec![pin]),
+            res => panic!("unexpected result: {:?}", res.err()),
+        }
+
+        // Pin and chain validation
+        let opts = ConnectOptions::new().tls(config.pin_spki_sha256(pin));
+        assert!(crate::native::connect(&url, &opts).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_certificate_pinning_extra_certificate() {
+        // The pinned certificate is sent as an unused extra certificate
+        let pinned = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+        let pin: [u8; 32] = pin::spki_sha256(pinned.cert.der()).unwrap();
+
+        // Pin only: the attacker's self-signed certificate must not match
+        let attacker = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+        let key = PrivatePkcs8KeyDer::from(attacker.key_pair.serialize_der());
+        let certs = vec![attacker.cert.der().clone(), pinned.cert.der().clone()];
+        let url = spawn_server_with_chain(certs, key, None).await;
+
+        let config = TlsConfig::new().rustls(client_config(RootCertStore::empty()));
+        let opts = ConnectOptions::new().tls(config.pin_spki_sha256(pin).pins_only(true));
+        assert!(matches!(
+            crate::native::connect(&url, &opts).await,
+            Err(Error::CertificatePinMismatch { .. })
+        ));
+
+        // Chain validation: a valid certificate from a trusted CA must not match either
+        let (ca, cert, key) = generate_ca_chain();
+        let certs = vec![cert, ca.clone(), pinned.cert.der().clone()];
+        let url = spawn_server_with_chain(certs, key, None).await;
+
+        let mut roots = RootCertStore::empty();
+        roots.add(ca.clone()).unwrap();
+        let config = TlsConfig::new().rustls(client_config(roots));
+        let opts = ConnectOptions::new().tls(config.clone().pin_spki_sha256(pin));
+        assert!(matches!(
+            crate::native::connect(&url, &opts).await,
+            Err(Error::CertificatePinMismatch { .. })
+        ));
+
+        // The CA is in the validated chain, so it can be pinned
+        let ca_pin: [u8; 32] = pin::spki_sha256(&ca).unwrap();
+        let opts = ConnectOptions::new().tls(config.pin_spki_sha256(ca_pin));
+        assert!(crate::native::connect(&url, &opts).await.is_ok());
+    }
+
+    #[test]
+    fn test_spki_sha256() {
+        use sha2::{Digest, Sha256};
+
+        let cert = rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+        let expected: [u8; 32] = Sha256::digest(cert.key_pair.public_key_der()).into();
+        assert_eq!(pin::spki_sha256(cert.cert.der()), Some(expected));
+        assert_eq!(pin::spki_sha256(&[0x30, 0x03, 0x02]), None);
+    }
+
+    #[tokio::test]
+    #[cfg(feature = "danger-insecure-tls")]
+    async fn test_danger_accept_invalid_certs() {
+        let (url, _) = spawn_server(None).await;
+
+        let config = TlsConfig::new()
+            .rustls(client_config(RootCertStore::empty()))
+            .danger_accept_invalid_certs(true);
+        assert!(format!("{config:?}").contains("INSECURE"));
+
+        let opts = ConnectOptions::new().tls(config);
+        assert!(crate::native::connect(&url, &opts).await.is_ok());
+    }
+}
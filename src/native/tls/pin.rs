@@ -0,0 +1,115 @@
+// Copyright (c) 2022-2024 Yuki Kishimoto
+// Distributed under the MIT software license
+
+//! Certificate pinning
+
+use sha2::{Digest, Sha256};
+
+use crate::native::Error;
+
+/// Verify that at least one of the pinnable server certificates matches a pin
+///
+/// The pinnable certificates are collected by the backend: only the ones proven to belong
+/// to the server identity can be matched, never the extra certificates sent by the server.
+pub(super) fn verify(pinnable: Vec<[u8; 32]>, pins: &[[u8; 32]]) -> Result<(), Error> {
+    if pinnable.iter().any(|pin| pins.contains(pin)) {
+        Ok(())
+    } else {
+        Err(Error::CertificatePinMismatch {
+            presented: pinnable,
+        })
+    }
+}
+
+/// Compute the SHA-256 hash of the DER-encoded SubjectPublicKeyInfo of a certificate
+pub(super) fn spki_sha256(cert: &[u8]) -> Option<[u8; 32]> {
+    let spki: &[u8] = spki(cert)?;
+    Some(Sha256::digest(spki).into())
+}
+
+/// Extract the DER-encoded SubjectPublicKeyInfo (tag and length included) from a DER-encoded certificate
+///
+/// ```text
+/// Certificate ::= SEQUENCE {
+///     tbsCertificate SEQUENCE {
+///         version         [0] EXPLICIT Version DEFAULT v1,
+///         serialNumber    INTEGER,
+///         signature       AlgorithmIdentifier,
+///         issuer          Name,
+///         validity        Validity,
+///         subject         Name,
+///         subjectPublicKeyInfo SubjectPublicKeyInfo,
+///         ...
+///     },
+///     ...
+/// }
+/// ```
+fn spki(cert: &[u8]) -> Option<&[u8]> {
+    let cert: Tlv = read_tlv(cert)?;
+    let tbs: Tlv = read_tlv(cert.value)?;
+    let mut fields: &[u8] = tbs.value;
+
+    // Skip the optional version
+    if fields.first() == Some(&VERSION) {
+        fields = read_tlv(fields)?.rest;
+    }
+
+    // Skip serial number, signature, issuer, validity and subject
+    for _ in 0..5 {
+        fields = read_tlv(fields)?.rest;
+    }
+
+    let spki: Tlv = read_tlv(fields)?;
+    if spki.tag == SEQUENCE {
+        Some(spki.raw)
+    } else {
+        None
+    }
+}
+
+const SEQUENCE: u8 = 0x30;
+const VERSION: u8 = 0xa0;
+
+/// DER TLV
+struct Tlv<'a> {
+    tag: u8,
+    /// The whole TLV
+    raw: &'a [u8],
+    value: &'a [u8],
+    /// The remaining input
+    rest: &'a [u8],
+}
+
+/// Read a DER TLV
+fn read_tlv(input: &[u8]) -> Option<Tlv<'_>> {
+    let (tag, rest) = input.split_first()?;
+    let (first, mut rest) = rest.split_first()?;
+
+    let len: usize = if first & 0x80 == 0 {
+        usize::from(*first)
+    } else {
+        // Long form: the low bits are the number of length bytes
+        let n: usize = usize::from(first & 0x7f);
+        if n == 0 || n > 4 || rest.len() < n {
+            return None;
+        }
+        let (bytes, r) = rest.split_at(n);
+        rest = r;
+        bytes
+            .iter()
+            .fold(0usize, |len, b| (len << 8) | usize::from(*b))
+    };
+
+    if rest.len() < len {
+        return None;
+    }
+
+    let header_len: usize = input.len() - rest.len();
+    let (value, rest) = rest.split_at(len);
+    Some(Tlv {
+        tag: *tag,
+        raw: &input[..header_len + len],
+        value,
+        rest,
+    })
+}
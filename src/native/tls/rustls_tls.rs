@@ -0,0 +1,427 @@
+// Copyright (c) 2022-2024 Yuki Kishimoto
+// Distributed under the MIT software license
+
+//! Rustls
+
+use std::io;
+use std::sync::Arc;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::rustls::client::danger::{
+    HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier,
+};
+use tokio_rustls::rustls::client::{ResolvesClientCert, WebPkiServerVerifier};
+use tokio_rustls::rustls::crypto::{
+    verify_tls12_signature, verify_tls13_signature, CryptoProvider,
+};
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use tokio_rustls::rustls::sign::{CertifiedKey, SigningKey};
+use tokio_rustls::rustls::{
+    AlertDescription, CertificateError, ClientConfig, DigitallySignedStruct, Error as RustlsError,
+    RootCertStore, SignatureScheme,
+};
+use tokio_rustls::TlsConnector;
+use tokio_tungstenite::tungstenite::error::TlsError;
+#[cfg(not(any(
+    feature = "native-tls",
+    feature = "rustls-tls-native-roots",
+    feature = "rustls-tls-webpki-roots"
+)))]
+use tokio_tungstenite::tungstenite::error::UrlError;
+use tokio_tungstenite::tungstenite::Error as WsError;
+use tokio_tungstenite::MaybeTlsStream;
+
+use super::identity::InnerIdentity;
+use super::{pin, ClientIdentity, TlsConfig};
+use crate::native::Error;
+
+pub(super) async fn connect<S>(
+    domain: &str,
+    stream: S,
+    mut config: Arc<ClientConfig>,
+    tls: &TlsConfig,
+) -> Result<MaybeTlsStream<S>, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    if let Some(identity) = &tls.identity {
+        config = with_client_identity(config, identity)?;
+    }
+
+    // The pins are verified after the handshake
+    if tls.skip_chain_validation() {
+        config = without_chain_validation(config);
+    }
+
+    let domain: ServerName<'static> = ServerName::try_from(domain.to_string())
+        .map_err(|_| WsError::Tls(TlsError::InvalidDnsName))?;
+    let connector: TlsConnector = TlsConnector::from(config.clone());
+    let stream = connector.connect(domain.clone(), stream).await?;
+
+    if !tls.pins.is_empty() {
+        let (_, conn) = stream.get_ref();
+        let certs: &[CertificateDer<'_>] = conn.peer_certificates().unwrap_or_default();
+        let pinnable: Vec<[u8; 32]> = pinnable_certificates(certs, &config, tls, &domain);
+        pin::verify(pinnable, &tls.pins)?;
+    }
+
+    Ok(MaybeTlsStream::Rustls(stream))
+}
+
+/// Compute the SPKI SHA-256 pins of the server certificates proven to belong to the server identity
+///
+/// The end-entity certificate is always pinnable, since its key signed the handshake.
+/// With chain validation, a presented intermediate is pinnable only if the end-entity certificate
+/// chains up to it: the extra certificates sent by the server are never matched.
+fn pinnable_certificates(
+    certs: &[CertificateDer<'_>],
+    config: &ClientConfig,
+    tls: &TlsConfig,
+    server_name: &ServerName<'_>,
+) -> Vec<[u8; 32]> {
+    let (end_entity, intermediates) = match certs.split_first() {
+        Some(certs) => certs,
+        None => return Vec::new(),
+    };
+
+    let mut pins: Vec<[u8; 32]> = pin::spki_sha256(end_entity).into_iter().collect();
+
+    if tls.skip_chain_validation() {
+        return pins;
+    }
+
+    for (index, cert) in intermediates.iter().enumerate() {
+        let others: Vec<CertificateDer<'_>> = intermediates
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != index)
+            .map(|(_, cert)| cert.clone())
+            .collect();
+
+        if chains_up_to(end_entity, &others, cert, config, server_name) {
+            pins.extend(pin::spki_sha256(cert));
+        }
+    }
+
+    pins
+}
+
+/// Check if the end-entity certificate chains up to the anchor, through the intermediates
+fn chains_up_to(
+    end_entity: &CertificateDer<'_>,
+    intermediates: &[CertificateDer<'_>],
+    anchor: &CertificateDer<'_>,
+    config: &ClientConfig,
+    server_name: &ServerName<'_>,
+) -> bool {
+    let mut roots: RootCertStore = RootCertStore::empty();
+    if roots.add(anchor.clone().into_owned()).is_err() {
+        return false;
+    }
+
+    let provider: Arc<CryptoProvider> = config.crypto_provider().clone();
+    let verifier =
+        match WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider).build() {
+            Ok(verifier) => verifier,
+            Err(..) => return false,
+        };
+
+    // The hostname has already been verified (or deliberately skipped) during the handshake
+    matches!(
+        verifier.verify_server_cert(end_entity, intermediates, server_name, &[], UnixTime::now()),
+        Ok(..)
+            | Err(RustlsError::InvalidCertificate(
+                CertificateError::NotValidForName
+            ))
+    )
+}
+
+/// Build the default client config, using the roots and the crypto provider enabled by the crate features
+#[cfg(not(feature = "native-tls"))]
+pub(super) fn default_config(tls: &TlsConfig) -> Result<Arc<ClientConfig>, Error> {
+    // Without roots, every certificate chain would be rejected
+    #[cfg(not(any(
+        feature = "rustls-tls-native-roots",
+        feature = "rustls-tls-webpki-roots"
+    )))]
+    if !tls.skip_chain_validation() {
+        return Err(WsError::Url(UrlError::TlsFeatureNotEnabled).into());
+    }
+
+    #[allow(unused_mut)]
+    let mut root_store: RootCertStore = RootCertStore::empty();
+
+    #[cfg(feature = "rustls-tls-native-roots")]
+    {
+        let certs = rustls_native_certs::load_native_certs().certs;
+        root_store.add_parsable_certificates(certs);
+    }
+
+    #[cfg(feature = "rustls-tls-webpki-roots")]
+    root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+
+    let provider: Arc<CryptoProvider> = crypto_provider()?;
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| WsError::Tls(e.into()))?;
+
+    let config: ClientConfig = if tls.skip_hostname_validation() {
+        let inner = WebPkiServerVerifier::builder_with_provider(Arc::new(root_store), provider)
+            .build()
+            .map_err(|e| WsError::Tls(RustlsError::General(e.to_string()).into()))?;
+        builder
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(NoHostnameVerifier { inner }))
+            .with_no_client_auth()
+    } else {
+        builder
+            .with_root_certificates(root_store)
+            .with_no_client_auth()
+    };
+
+    Ok(Arc::new(config))
+}
+
+/// Get the crypto provider enabled by the crate features
+///
+/// `aws_lc_rs` is preferred if both `aws_lc_rs` and `ring` are enabled.
+/// If none of them is enabled, the process-level default provider is used.
+#[cfg(not(feature = "native-tls"))]
+fn crypto_provider() -> Result<Arc<CryptoProvider>, Error> {
+    #[cfg(feature = "aws_lc_rs")]
+    {
+        Ok(Arc::new(
+            tokio_rustls::rustls::crypto::aws_lc_rs::default_provider(),
+        ))
+    }
+
+    #[cfg(all(feature = "ring", not(feature = "aws_lc_rs")))]
+    {
+        Ok(Arc::new(
+            tokio_rustls::rustls::crypto::ring::default_provider(),
+        ))
+    }
+
+    #[cfg(not(any(feature = "aws_lc_rs", feature = "ring")))]
+    {
+        CryptoProvider::get_default().cloned().ok_or_else(|| {
+            let e = RustlsError::General(String::from("no process-level crypto provider"));
+            WsError::Tls(e.into()).into()
+        })
+    }
+}
+
+/// Clone the config, replacing the client certificate resolver
+fn with_client_identity(
+    config: Arc<ClientConfig>,
+    identity: &ClientIdentity,
+) -> Result<Arc<ClientConfig>, Error> {
+    let (cert_chain, key) = match &identity.inner {
+        InnerIdentity::Pem { cert_chain, key } => {
+            let cert_chain: Vec<CertificateDer<'static>> =
+                CertificateDer::pem_slice_iter(cert_chain)
+                    .collect::<Result<_, _>>()
+                    .map_err(|e| Error::InvalidClientIdentity(e.to_string()))?;
+            if cert_chain.is_empty() {
+                return Err(Error::InvalidClientIdentity(String::from(
+                    "no PEM certificate found",
+                )));
+            }
+            let key: PrivateKeyDer<'static> = PrivateKeyDer::from_pem_slice(key)
+                .map_err(|e| Error::InvalidClientIdentity(e.to_string()))?;
+            (cert_chain, key)
+        }
+        InnerIdentity::Der { cert_chain, key } => {
+            if cert_chain.is_empty() {
+                return Err(Error::InvalidClientIdentity(String::from(
+                    "no certificate found",
+                )));
+            }
+            let cert_chain: Vec<CertificateDer<'static>> = cert_chain
+                .iter()
+                .map(|cert| CertificateDer::from(cert.clone()))
+                .collect();
+            let key: PrivateKeyDer<'static> = PrivateKeyDer::try_from(key.as_slice())
+                .map_err(|e| Error::InvalidClientIdentity(e.to_string()))?
+                .clone_key();
+            (cert_chain, key)
+        }
+        #[cfg(feature = "native-tls")]
+        InnerIdentity::Pkcs12 { .. } => {
+            return Err(Error::InvalidClientIdentity(String::from(
+                "PKCS#12 is not supported by rustls, use PEM instead",
+            )));
+        }
+    };
+
+    let key: Arc<dyn SigningKey> = config
+        .crypto_provider()
+        .key_provider
+        .load_private_key(key)
+        .map_err(|e| Error::InvalidClientIdentity(e.to_string()))?;
+
+    let mut config: ClientConfig = config.as_ref().clone();
+    config.client_auth_cert_resolver = Arc::new(ClientCertResolver {
+        key: Arc::new(CertifiedKey::new(cert_chain, key)),
+    });
+    Ok(Arc::new(config))
+}
+
+/// Clone the config, replacing the server certificate verifier with one that doesn't validate the chain
+fn without_chain_validation(config: Arc<ClientConfig>) -> Arc<ClientConfig> {
+    let provider: Arc<CryptoProvider> = config.crypto_provider().clone();
+    let mut config: ClientConfig = config.as_ref().clone();
+    config
+        .dangerous()
+        .set_certificate_verifier(Arc::new(NoChainVerifier { provider }));
+    Arc::new(config)
+}
+
+/// Server certificate verifier that accepts any certificate chain
+///
+/// The handshake signatures are still verified, so the server must own the private key
+/// of the presented certificate.
+#[derive(Debug)]
+struct NoChainVerifier {
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for NoChainVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, RustlsError> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, RustlsError> {
+        verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, RustlsError> {
+        verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+/// Server certificate verifier that validates the chain but accepts any hostname
+#[cfg(not(feature = "native-tls"))]
+#[derive(Debug)]
+struct NoHostnameVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+}
+
+#[cfg(not(feature = "native-tls"))]
+impl ServerCertVerifier for NoHostnameVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, RustlsError> {
+        match self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        ) {
+            Err(RustlsError::InvalidCertificate(CertificateError::NotValidForName)) => {
+                Ok(ServerCertVerified::assertion())
+            }
+            res => res,
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, RustlsError> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, RustlsError> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// Always resolve the same client certificate
+#[derive(Debug)]
+struct ClientCertResolver {
+    key: Arc<CertifiedKey>,
+}
+
+impl ResolvesClientCert for ClientCertResolver {
+    fn resolve(
+        &self,
+        _root_hint_subjects: &[&[u8]],
+        _sigschemes: &[SignatureScheme],
+    ) -> Option<Arc<CertifiedKey>> {
+        Some(self.key.clone())
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+/// Check if the error is an alert sent by the server because of the client certificate
+pub(super) fn is_client_certificate_rejected(e: &io::Error) -> bool {
+    match e.get_ref().and_then(|e| e.downcast_ref::<RustlsError>()) {
+        Some(RustlsError::AlertReceived(alert)) => matches!(
+            alert,
+            AlertDescription::BadCertificate
+                | AlertDescription::UnsupportedCertificate
+                | AlertDescription::CertificateRevoked
+                | AlertDescription::CertificateExpired
+                | AlertDescription::CertificateUnknown
+                | AlertDescription::CertificateRequired
+                | AlertDescription::UnknownCA
+                | AlertDescription::AccessDenied
+        ),
+        _ => false,
+    }
+}
@@ -0,0 +1,328 @@
+// Copyright (c) 2022-2024 Yuki Kishimoto
+// Distributed under the MIT software license
+
+//! Connect options
+
+use std::fmt;
+#[cfg(not(target_arch = "wasm32"))]
+use std::net::{IpAddr, SocketAddr};
+#[cfg(not(target_arch = "wasm32"))]
+use std::ops::RangeInclusive;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::native::{HappyEyeballsConfig, Resolver, TcpOptions, TlsConfig};
+use crate::{ConnectionMode, WebSocketConfig};
+
+/// The phase of the connection process that timed out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TimeoutPhase {
+    /// The whole connection process
+    Total,
+    /// DNS resolution and TCP connection
+    #[cfg(not(target_arch = "wasm32"))]
+    Connect,
+    /// Proxy negotiation
+    #[cfg(not(target_arch = "wasm32"))]
+    Proxy,
+    /// TLS handshake
+    #[cfg(not(target_arch = "wasm32"))]
+    Tls,
+    /// HTTP upgrade (WebSocket handshake)
+    #[cfg(not(target_arch = "wasm32"))]
+    Handshake,
+    /// Waiting for the browser to open the connection
+    #[cfg(target_arch = "wasm32")]
+    Open,
+}
+
+impl fmt::Display for TimeoutPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Total => write!(f, "connection"),
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Connect => write!(f, "TCP connect"),
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Proxy => write!(f, "proxy negotiation"),
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Tls => write!(f, "TLS handshake"),
+            #[cfg(not(target_arch = "wasm32"))]
+            Self::Handshake => write!(f, "WebSocket handshake"),
+            #[cfg(target_arch = "wasm32")]
+            Self::Open => write!(f, "open"),
+        }
+    }
+}
+
+/// Options used to establish a WebSocket connection
+#[derive(Debug, Clone, Default)]
+pub struct ConnectOptions {
+    pub(crate) mode: ConnectionMode,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) protocols: Vec<String>,
+    pub(crate) config: WebSocketConfig,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) tls: TlsConfig,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) proxy_tls: TlsConfig,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) connect_to: Vec<SocketAddr>,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) resolver: Option<Arc<dyn Resolver>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) tcp: TcpOptions,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) happy_eyeballs: HappyEyeballsConfig,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) local_address: Option<IpAddr>,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) local_port_range: Option<RangeInclusive<u16>>,
+    #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+    pub(crate) interface: Option<String>,
+    pub(crate) timeout: Option<Duration>,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) connect_timeout: Option<Duration>,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) proxy_timeout: Option<Duration>,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) tls_timeout: Option<Duration>,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) handshake_timeout: Option<Duration>,
+    #[cfg(target_arch = "wasm32")]
+    pub(crate) open_timeout: Option<Duration>,
+}
+
+impl ConnectOptions {
+    /// New default options
+    #[inline]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set connection mode (default: [`ConnectionMode::Direct`])
+    #[inline]
+    pub fn mode(mut self, mode: ConnectionMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Add an extra header to the handshake request
+    ///
+    /// Not supported in WASM environments: the connection fails with `Error::UnsupportedOption`.
+    #[inline]
+    pub fn header<K, V>(mut self, name: K, value: V) -> Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Add extra headers to the handshake request
+    ///
+    /// Headers with the same name of the default ones (i.e., `Host`) replace them.
+    ///
+    /// Not supported in WASM environments: the connection fails with `Error::UnsupportedOption`.
+    pub fn headers<I, K, V>(mut self, headers: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.headers
+            .extend(headers.into_iter().map(|(k, v)| (k.into(), v.into())));
+        self
+    }
+
+    /// Set the subprotocols to request during the handshake (`Sec-WebSocket-Protocol`)
+    ///
+    /// On all the targets, the connection fails with a [`SubProtocolError`](crate::SubProtocolError)
+    /// if the server doesn't select exactly one of the requested subprotocols,
+    /// or selects one when none was requested.
+    pub fn protocols<I, S>(mut self, protocols: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.protocols = protocols.into_iter().map(|p| p.into()).collect();
+        self
+    }
+
+    /// Set the WebSocket protocol config
+    #[inline]
+    pub fn config(mut self, config: WebSocketConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Set the TLS config, used for `wss` URLs
+    #[inline]
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = tls;
+        self
+    }
+
+    /// Set the TLS config used to connect to HTTPS proxies
+    #[inline]
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn proxy_tls(mut self, tls: TlsConfig) -> Self {
+        self.proxy_tls = tls;
+        self
+    }
+
+    /// Connect to these addresses instead of resolving the URL host (default: none)
+    ///
+    /// The URL host is still used for the TLS SNI, the certificate verification and the `Host` header
+    /// (like curl's `--connect-to`). When connecting through a proxy, only the first address is used
+    /// (the first IPv4 address with SOCKS4, which doesn't support IPv6).
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn connect_to<I>(mut self, addrs: I) -> Self
+    where
+        I: IntoIterator<Item = SocketAddr>,
+    {
+        self.connect_to = addrs.into_iter().collect();
+        self
+    }
+
+    /// Set the DNS resolver (default: [`SystemResolver`](crate::native::SystemResolver))
+    ///
+    /// When connecting through a proxy, only used with `ProxyDns::Local`.
+    #[inline]
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn resolver(mut self, resolver: Arc<dyn Resolver>) -> Self {
+        self.resolver = Some(resolver);
+        self
+    }
+
+    /// Set the TCP socket options, applied before connecting
+    #[inline]
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn tcp(mut self, tcp: TcpOptions) -> Self {
+        self.tcp = tcp;
+        self
+    }
+
+    /// Set the Happy Eyeballs config, used when the host resolves to multiple addresses
+    #[inline]
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn happy_eyeballs(mut self, config: HappyEyeballsConfig) -> Self {
+        self.happy_eyeballs = config;
+        self
+    }
+
+    /// Bind the outbound TCP connections to a local address (default: none)
+    ///
+    /// Only the remote addresses of the same family are used: the others are skipped.
+    /// Applies to both direct and proxy connections.
+    #[inline]
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn local_address(mut self, addr: IpAddr) -> Self {
+        self.local_address = Some(addr);
+        self
+    }
+
+    /// Bind the outbound TCP connections to the first free local port of the range (default: none)
+    #[inline]
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn local_port_range(mut self, ports: RangeInclusive<u16>) -> Self {
+        self.local_port_range = Some(ports);
+        self
+    }
+
+    /// Bind the outbound TCP connections to a network interface, using `SO_BINDTODEVICE` (default: none)
+    ///
+    /// May require the `CAP_NET_RAW` capability.
+    #[inline]
+    #[cfg(any(target_os = "android", target_os = "fuchsia", target_os = "linux"))]
+    pub fn interface<S>(mut self, interface: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.interface = Some(interface.into());
+        self
+    }
+
+    /// Set the timeout for the whole connection process (default: none)
+    #[inline]
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Set the timeout for the DNS resolution and the TCP connection (default: none)
+    ///
+    /// With a proxy, this isn't used: the connection to the proxy is covered by [`ConnectOptions::proxy_timeout`].
+    #[inline]
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the timeout for the proxy negotiation (default: none)
+    ///
+    /// Covers the whole tunnel setup, for all the hops of a proxy chain: the DNS resolution of the proxy host
+    /// (and of the server with local DNS), the TCP connection to the proxy, the TLS handshake with an HTTPS proxy
+    /// and the proxy handshake.
+    /// The TLS handshake and the HTTP upgrade with the server are then covered by [`ConnectOptions::tls_timeout`]
+    /// and [`ConnectOptions::handshake_timeout`].
+    #[inline]
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn proxy_timeout(mut self, timeout: Duration) -> Self {
+        self.proxy_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the timeout for the TLS handshake (default: none)
+    #[inline]
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn tls_timeout(mut self, timeout: Duration) -> Self {
+        self.tls_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the timeout for the HTTP upgrade (default: none)
+    #[inline]
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = Some(timeout);
+        self
+    }
+
+    /// Set the timeout for the browser to open the connection (default: none)
+    #[inline]
+    #[cfg(target_arch = "wasm32")]
+    pub fn open_timeout(mut self, timeout: Duration) -> Self {
+        self.open_timeout = Some(timeout);
+        self
+    }
+
+    /// Get the first set option that browsers can't honor, if any
+    ///
+    /// Browsers don't allow to set custom headers for the handshake request.
+    #[cfg(any(target_arch = "wasm32", test))]
+    pub(crate) fn unsupported_in_browser(&self) -> Option<&'static str> {
+        if !self.headers.is_empty() {
+            return Some("headers");
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unsupported_in_browser() {
+        assert_eq!(ConnectOptions::new().unsupported_in_browser(), None);
+
+        let opts = ConnectOptions::new().header("Authorization", "Bearer token");
+        assert_eq!(opts.unsupported_in_browser(), Some("headers"));
+    }
+}
@@ -0,0 +1,89 @@
+// Copyright (c) 2022-2024 Yuki Kishimoto
+// Distributed under the MIT software license
+
+//! Handshake response
+
+#[cfg(not(target_arch = "wasm32"))]
+use tokio_tungstenite::tungstenite::handshake::client::Response;
+#[cfg(not(target_arch = "wasm32"))]
+use tokio_tungstenite::tungstenite::http::header::{
+    SEC_WEBSOCKET_EXTENSIONS, SEC_WEBSOCKET_PROTOCOL,
+};
+#[cfg(not(target_arch = "wasm32"))]
+use tokio_tungstenite::tungstenite::http::{HeaderMap, StatusCode};
+
+/// The response received from the server during the WebSocket handshake
+#[derive(Debug, Clone)]
+pub struct HandshakeResponse {
+    #[cfg(not(target_arch = "wasm32"))]
+    status: StatusCode,
+    #[cfg(not(target_arch = "wasm32"))]
+    headers: HeaderMap,
+    protocol: Option<String>,
+    extensions: Option<String>,
+}
+
+impl HandshakeResponse {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn native(response: Response) -> Self {
+        let (parts, _) = response.into_parts();
+
+        let header = |name| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from)
+        };
+
+        Self {
+            protocol: header(SEC_WEBSOCKET_PROTOCOL),
+            extensions: header(SEC_WEBSOCKET_EXTENSIONS),
+            status: parts.status,
+            headers: parts.headers,
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub(crate) fn wasm(protocol: Option<String>, extensions: Option<String>) -> Self {
+        Self {
+            protocol,
+            extensions,
+        }
+    }
+
+    /// HTTP status code
+    #[inline]
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// HTTP response headers
+    #[inline]
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// Get the value of a response header
+    ///
+    /// Returns `None` if the header is missing or if it's not a valid string.
+    #[inline]
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|v| v.to_str().ok())
+    }
+
+    /// Subprotocol selected by the server (`Sec-WebSocket-Protocol`)
+    #[inline]
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+
+    /// Extensions selected by the server (`Sec-WebSocket-Extensions`)
+    #[inline]
+    pub fn extensions(&self) -> Option<&str> {
+        self.extensions.as_deref()
+    }
+}
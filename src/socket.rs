@@ -6,55 +6,92 @@ use std::task::{Context, Poll};
 
 use futures_util::{Sink, Stream};
 #[cfg(not(target_arch = "wasm32"))]
-use tokio::net::TcpStream;
+use tokio::io::{AsyncRead, AsyncWrite};
 #[cfg(not(target_arch = "wasm32"))]
 use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
 use url::Url;
 
+#[cfg(not(target_arch = "wasm32"))]
+use crate::native::BoxedStream;
 #[cfg(target_arch = "wasm32")]
 use crate::wasm::WsStream;
-use crate::{ConnectionMode, Error, Message};
+use crate::{ConnectOptions, ConnectionMode, Error, HandshakeResponse, Message};
 
 #[cfg(not(target_arch = "wasm32"))]
 type WsStream<T> = WebSocketStream<MaybeTlsStream<T>>;
 
 enum InnerWebSocket {
     #[cfg(not(target_arch = "wasm32"))]
-    Tokio(Box<WsStream<TcpStream>>),
+    Tokio(Box<WsStream<BoxedStream>>),
     #[cfg(target_arch = "wasm32")]
     Wasm(WsStream),
 }
 
 pub struct WebSocket {
     inner: InnerWebSocket,
+    response: HandshakeResponse,
 }
 
 impl WebSocket {
     #[inline]
-    fn new(inner: InnerWebSocket) -> Self {
-        Self { inner }
+    fn new(inner: InnerWebSocket, response: HandshakeResponse) -> Self {
+        Self { inner, response }
     }
 
     #[inline]
     #[cfg(not(target_arch = "wasm32"))]
-    pub(crate) fn tokio(inner: Box<WsStream<TcpStream>>) -> Self {
-        Self::new(InnerWebSocket::Tokio(inner))
+    pub(crate) fn tokio(inner: Box<WsStream<BoxedStream>>, response: HandshakeResponse) -> Self {
+        Self::new(InnerWebSocket::Tokio(inner), response)
     }
     #[inline]
     #[cfg(target_arch = "wasm32")]
-    pub(crate) fn wasm(inner: WsStream) -> Self {
-        Self::new(InnerWebSocket::Wasm(inner))
+    pub(crate) fn wasm(inner: WsStream, response: HandshakeResponse) -> Self {
+        Self::new(InnerWebSocket::Wasm(inner), response)
+    }
+
+    pub async fn connect(url: &Url, mode: &ConnectionMode) -> Result<Self, Error> {
+        let opts: ConnectOptions = ConnectOptions::new().mode(mode.clone());
+        Self::connect_with(url, &opts).await
     }
 
-    pub async fn connect(url: &Url, _mode: &ConnectionMode) -> Result<Self, Error> {
+    /// Connect with custom [`ConnectOptions`]
+    pub async fn connect_with(url: &Url, opts: &ConnectOptions) -> Result<Self, Error> {
         #[cfg(not(target_arch = "wasm32"))]
-        let socket: WebSocket = crate::native::connect(url, _mode).await?;
+        let socket: WebSocket = crate::native::connect(url, opts).await?;
 
         #[cfg(target_arch = "wasm32")]
-        let socket: WebSocket = crate::wasm::connect(url).await?;
+        let socket: WebSocket = crate::wasm::connect(url, opts).await?;
 
         Ok(socket)
     }
+
+    /// Connect over an already established stream (i.e., an SSH channel or an in-process duplex)
+    ///
+    /// Performs the TLS handshake for `wss` URLs and the WebSocket upgrade.
+    /// The connection mode of the [`ConnectOptions`] is ignored.
+    #[inline]
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn client_over<S>(url: &Url, stream: S, opts: &ConnectOptions) -> Result<Self, Error>
+    where
+        S: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        crate::native::connect_over(url, stream, opts).await
+    }
+
+    /// Get the response received from the server during the handshake
+    #[inline]
+    pub fn handshake_response(&self) -> &HandshakeResponse {
+        &self.response
+    }
+
+    /// Get the subprotocol selected by the server, if any
+    ///
+    /// This is always one of [`ConnectOptions::protocols`](crate::ConnectOptions::protocols),
+    /// and is `None` only if no subprotocol was requested.
+    #[inline]
+    pub fn protocol(&self) -> Option<&str> {
+        self.response.protocol()
+    }
 }
 
 impl Sink<Message> for WebSocket {
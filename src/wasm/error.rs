@@ -6,6 +6,7 @@ use core::fmt;
 use core::str::Utf8Error;
 
 use crate::wasm::CloseEvent;
+use crate::{SubProtocolError, TimeoutPhase};
 
 /// WebSocket Error
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -57,9 +58,24 @@ pub enum Error {
     ///
     /// Happens in `impl TryFrom< MessageEvent > for WsMessage`.
     UnknownDataType,
+    /// Subprotocol negotiation failed.
+    SubProtocol(SubProtocolError),
+    /// The received message exceeds the max message size.
+    MessageTooLong {
+        /// The size of the received message.
+        size: usize,
+        /// The max allowed size.
+        max_size: usize,
+    },
+    /// The option can't be honored in WASM environments.
+    UnsupportedOption {
+        /// The name of the unsupported option.
+        option: &'static str,
+    },
     Dom(u16),
     Other(String),
-    Timeout,
+    /// Timeout
+    Timeout(TimeoutPhase),
 }
 
 impl std::error::Error for Error {}
@@ -95,9 +111,19 @@ impl fmt::Display for Error {
                 f,
                 "Received a message that is neither ArrayBuffer, String or Blob."
             ),
+            Self::SubProtocol(e) => write!(f, "{e}"),
+            Self::MessageTooLong { size, max_size } => {
+                write!(f, "Message too long: {size} > {max_size}")
+            }
+            Self::UnsupportedOption { option } => {
+                write!(
+                    f,
+                    "The `{option}` option is not supported in WASM environments."
+                )
+            }
             Self::Dom(code) => write!(f, "DOM Exception: {code}"),
             Self::Other(e) => write!(f, "{e}"),
-            Self::Timeout => write!(f, "timeout"),
+            Self::Timeout(phase) => write!(f, "{phase} timeout"),
         }
     }
 }
@@ -6,6 +6,12 @@
 
 #![allow(clippy::arc_with_non_send_sync)]
 
+use std::future::Future;
+use std::pin::pin;
+use std::time::Duration;
+
+use futures::future::{self, Either};
+use gloo_timers::future::TimeoutFuture;
 use url::Url;
 use wasm_bindgen_futures::spawn_local;
 
@@ -24,10 +30,62 @@ use self::socket::WebSocket as WasmWebSocket;
 use self::state::WsState;
 pub(crate) use self::stream::WsStream;
 use crate::socket::WebSocket;
+use crate::{ConnectOptions, HandshakeResponse, SubProtocolError, TimeoutPhase};
+
+pub async fn connect(url: &Url, opts: &ConnectOptions) -> Result<WebSocket, Error> {
+    if let Some(option) = opts.unsupported_in_browser() {
+        return Err(Error::UnsupportedOption { option });
+    }
+
+    timeout(
+        opts.timeout,
+        TimeoutPhase::Total,
+        connect_and_verify(url, opts),
+    )
+    .await
+}
+
+async fn connect_and_verify(url: &Url, opts: &ConnectOptions) -> Result<WebSocket, Error> {
+    let (ws, stream) = timeout(
+        opts.open_timeout,
+        TimeoutPhase::Open,
+        WasmWebSocket::connect(url, &opts.protocols, opts.config),
+    )
+    .await?;
+
+    // An empty string means that the server didn't select any subprotocol.
+    // If an error is returned, the connection is closed when dropping the stream.
+    let non_empty = |s: String| if s.is_empty() { None } else { Some(s) };
+    let protocol: Option<String> = non_empty(ws.protocol());
+    SubProtocolError::check(&opts.protocols, protocol.as_deref()).map_err(Error::SubProtocol)?;
+
+    let response: HandshakeResponse = HandshakeResponse::wasm(protocol, non_empty(ws.extensions()));
+
+    Ok(WebSocket::wasm(stream, response))
+}
+
+/// Run the future until completion or until the timeout expires
+async fn timeout<F, T>(
+    duration: Option<Duration>,
+    phase: TimeoutPhase,
+    future: F,
+) -> Result<T, Error>
+where
+    F: Future<Output = Result<T, Error>>,
+{
+    let Some(duration) = duration else {
+        return future.await;
+    };
 
-pub async fn connect(url: &Url) -> Result<WebSocket, Error> {
-    let (_ws, stream) = WasmWebSocket::connect(url).await?;
-    Ok(WebSocket::wasm(stream))
+    // `setTimeout` takes a signed 32-bit delay: larger values would fire immediately
+    let millis: u32 = u32::try_from(duration.as_millis())
+        .unwrap_or(u32::MAX)
+        .min(i32::MAX as u32);
+    let future = pin!(future);
+    match future::select(future, TimeoutFuture::new(millis)).await {
+        Either::Left((res, _)) => res,
+        Either::Right(..) => Err(Error::Timeout(phase)),
+    }
 }
 
 /// Helper function to reduce code bloat
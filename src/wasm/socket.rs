@@ -6,13 +6,15 @@ use std::fmt;
 use std::sync::Arc;
 
 use futures::StreamExt;
+use js_sys::Array;
 use url::Url;
 use wasm_bindgen::closure::Closure;
-use wasm_bindgen::JsCast;
+use wasm_bindgen::{JsCast, JsValue};
 use web_sys::{BinaryType, CloseEvent as JsCloseEvt, DomException, WebSocket as WebSysSocket};
 
 use crate::wasm::pharos::{Filter, Observable, Observe, ObserveConfig, PharErr, SharedPharos};
 use crate::wasm::{notify, CloseEvent, Error, WsEvent, WsState, WsStream};
+use crate::WebSocketConfig;
 
 /// The metadata related to a websocket. Allows access to the methods on the WebSocket API.
 /// This is split from the `Stream`/`Sink` so you can pass the latter to a combinator whilst
@@ -33,8 +35,19 @@ impl WebSocket {
 
     /// Connect to the server. The future will resolve when the connection has been established with a successful WebSocket
     /// handshake.
-    pub async fn connect(url: &Url) -> Result<(Self, WsStream), Error> {
-        let ws: Arc<WebSysSocket> = match WebSysSocket::new(url.as_str()) {
+    pub async fn connect(
+        url: &Url,
+        protocols: &[String],
+        config: WebSocketConfig,
+    ) -> Result<(Self, WsStream), Error> {
+        let ws: Result<WebSysSocket, JsValue> = if protocols.is_empty() {
+            WebSysSocket::new(url.as_str())
+        } else {
+            let protocols: Array = protocols.iter().map(|p| JsValue::from_str(p)).collect();
+            WebSysSocket::new_with_str_sequence(url.as_str(), &protocols)
+        };
+
+        let ws: Arc<WebSysSocket> = match ws {
             Ok(ws) => Arc::new(ws),
             Err(e) => {
                 let de: &DomException = e.unchecked_ref();
@@ -105,8 +118,11 @@ impl WebSocket {
                     self.ws.set_onclose(None);
                     self.ws.set_onerror(None);
 
-                    // Check if connection is `OPEN`. Will cause a panic if is not `open`
-                    if let Ok(WsState::Open) = self.ws.ready_state().try_into() {
+                    // Close the connection if it's `CONNECTING` or `OPEN`, otherwise the browser would keep it
+                    // open without an owner (i.e., when a timeout drops this future).
+                    if let Ok(WsState::Connecting | WsState::Open) =
+                        self.ws.ready_state().try_into()
+                    {
                         let _ = self.ws.close();
                     }
 
@@ -152,6 +168,7 @@ impl WebSocket {
                 Arc::new(on_open),
                 Arc::new(on_error),
                 Arc::new(on_close),
+                config,
             ),
         ))
     }
@@ -160,6 +177,16 @@ impl WebSocket {
     pub fn url(&self) -> String {
         self.ws.url()
     }
+
+    /// The subprotocol selected by the server. Empty if none was selected.
+    pub fn protocol(&self) -> String {
+        self.ws.protocol()
+    }
+
+    /// The extensions selected by the server. Empty if none was selected.
+    pub fn extensions(&self) -> String {
+        self.ws.extensions()
+    }
 }
 
 impl fmt::Debug for WebSocket {
@@ -17,9 +17,11 @@ use wasm_bindgen::JsCast;
 use wasm_bindgen_futures::spawn_local;
 use web_sys::{CloseEvent as JsCloseEvt, WebSocket, *};
 
+use crate::config::MESSAGE_TOO_BIG;
 use crate::message::Message;
 use crate::wasm::pharos::{Filter, Observable, SharedPharos};
 use crate::wasm::{notify, Error, WsEvent, WsState};
+use crate::WebSocketConfig;
 
 /// A futures 0.3 Sink/Stream of [Message]. Created with [WsMeta::connect](crate::WsMeta::connect).
 ///
@@ -44,7 +46,7 @@ pub struct WsStream {
     ws: Arc<WebSocket>,
 
     // The queue of received messages
-    queue: Arc<RefCell<VecDeque<Message>>>,
+    queue: Arc<RefCell<VecDeque<Result<Message, Error>>>>,
 
     // Last waker of task that wants to read incoming messages to be woken up on a new message
     waker: Arc<RefCell<Option<Waker>>>,
@@ -73,6 +75,7 @@ impl WsStream {
         on_open: Arc<Closure<dyn FnMut()>>,
         on_error: Arc<Closure<dyn FnMut()>>,
         on_close: Arc<Closure<dyn FnMut(JsCloseEvt)>>,
+        config: WebSocketConfig,
     ) -> Self {
         let waker: Arc<RefCell<Option<Waker>>> = Arc::new(RefCell::new(None));
         let sink_waker: Arc<RefCell<Option<Waker>>> = Arc::new(RefCell::new(None));
@@ -81,12 +84,24 @@ impl WsStream {
         let q2 = queue.clone();
         let w2 = waker.clone();
         let ph2 = pharos.clone();
+        let ws2 = ws.clone();
 
         // Send the incoming ws messages to the WsMeta object
         #[allow(trivial_casts)]
         let on_msg = Closure::wrap(Box::new(move |msg_evt: MessageEvent| {
             match Message::try_from(msg_evt) {
-                Ok(msg) => q2.borrow_mut().push_back(msg),
+                Ok(msg) => match config.check_message_size(msg.len()) {
+                    // Same behavior of `tungstenite`: the message is discarded and the connection is failed
+                    Err(max_size) => {
+                        let _ = ws2.close_with_code_and_reason(MESSAGE_TOO_BIG, "Message too big");
+                        notify(ph2.clone(), WsEvent::Closing);
+                        q2.borrow_mut().push_back(Err(Error::MessageTooLong {
+                            size: msg.len(),
+                            max_size,
+                        }));
+                    }
+                    Ok(()) => q2.borrow_mut().push_back(Ok(msg)),
+                },
                 Err(err) => notify(ph2.clone(), WsEvent::WsErr(err)),
             }
 
@@ -204,7 +219,7 @@ impl Stream for WsStream {
             }
         } else {
             // As long as there is things in the queue, just keep reading
-            self.queue.borrow_mut().pop_front().map(Ok).into()
+            self.queue.borrow_mut().pop_front().into()
         }
     }
 }